@@ -0,0 +1,447 @@
+use std::io::{self, Read, Write};
+
+use crate::EncodingMethod;
+use crate::error::Error;
+use crate::archive::{Archive, Member, NamedFile};
+use crate::bitstream::{BitReader, BitWriter};
+
+
+// ---------- Alphabet -----------
+
+/// Symbol marking the end of a stream, right after the 256 byte values.
+const EOF_SYMBOL: u16 = 256;
+
+/// Number of bits used to write a symbol the first time it appears (256 bytes + end of stream).
+const RAW_SYMBOL_BITS: usize = 9;
+
+/// Number of symbols of the alphabet. With the NYT node, the tree holds at most `2 * ALPHABET_SIZE + 1` nodes.
+const ALPHABET_SIZE: usize = 257;
+
+
+// #################################
+// ------- Adaptive tree -----------
+// #################################
+
+/// Node of the adaptive tree. Children and parents are indices in `AdaptiveHuffman::nodes`.
+struct Node {
+    weight: u64,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    symbol: Option<u16>,
+}
+
+
+/// Adaptive Huffman model following the FGK algorithm (Faller, Gallager, Knuth).
+///
+/// The encoder and the decoder start from the same tree, holding only the NYT ("not yet transmitted") node, and
+/// update it the same way after each symbol, so no code table has to be stored. A symbol seen for the first time is
+/// sent as the code of the NYT node followed by its raw value.
+///
+/// Every node has a number, the root having the highest one, such that weights never decrease with the numbers
+/// (sibling property). Before the weight of a node is increased, it is swapped with the highest numbered node of
+/// the same weight, which keeps the property true.
+pub struct AdaptiveHuffman {
+    nodes: Vec<Node>,
+    /// Node of each symbol, if it was already seen
+    leaves: Vec<Option<usize>>,
+    /// `number[node]` is the number of a node, `order[number]` the node holding a number
+    number: Vec<usize>,
+    order: Vec<Option<usize>>,
+    nyt: usize,
+}
+
+impl AdaptiveHuffman {
+    pub fn new() -> AdaptiveHuffman {
+        let max_number = 2 * ALPHABET_SIZE;
+        let mut order = vec![None; max_number + 1];
+        order[max_number] = Some(0);
+
+        AdaptiveHuffman {
+            nodes: vec![Node { weight: 0, parent: None, children: None, symbol: None }],
+            leaves: vec![None; ALPHABET_SIZE],
+            number: vec![max_number],
+            order,
+            nyt: 0,
+        }
+    }
+
+    /// Returns the code of a node, by walking up from it to the root.
+    fn code_of(&self, mut node: usize, writer: &mut BitWriter) {
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            let (_, right) = self.nodes[parent].children.unwrap();
+            path.push(right == node);
+            node = parent;
+        }
+        for bit in path.into_iter().rev() {
+            writer.write_int(bit as u64, 1);
+        }
+    }
+
+    /// Writes the code of `symbol` and updates the tree.
+    pub fn encode_symbol(&mut self, symbol: u16, writer: &mut BitWriter) {
+        match self.leaves[symbol as usize] {
+            Some(leaf) => self.code_of(leaf, writer),
+            None => {
+                self.code_of(self.nyt, writer);
+                writer.write_int(symbol as u64, RAW_SYMBOL_BITS);
+            }
+        }
+        self.update(symbol);
+    }
+
+    /// Reads the next symbol of the stream and updates the tree.
+    pub fn decode_symbol(&mut self, reader: &mut BitReader) -> Result<u16, Error> {
+        // The root is never swapped, so it stays the first node
+        let mut node = 0;
+        while let Some((left, right)) = self.nodes[node].children {
+            node = if reader.read_bit()? { right } else { left };
+        }
+
+        let symbol = match self.nodes[node].symbol {
+            Some(symbol) => symbol,
+            None => {
+                let symbol = reader.read_int(RAW_SYMBOL_BITS)? as u16;
+                if symbol as usize >= ALPHABET_SIZE || self.leaves[symbol as usize].is_some() {
+                    return Err(Error::corrupt("invalid new symbol in adaptive Huffman stream"));
+                }
+                symbol
+            }
+        };
+        self.update(symbol);
+        Ok(symbol)
+    }
+
+    /// Adds one occurrence of `symbol` to the tree, creating its leaf if it is new.
+    fn update(&mut self, symbol: u16) {
+        let leaf = match self.leaves[symbol as usize] {
+            Some(leaf) => leaf,
+            None => {
+                // The NYT node becomes the parent of the new NYT node (left) and of the new leaf (right),
+                // which take the two numbers right below it
+                let old_nyt = self.nyt;
+                let nyt_number = self.number[old_nyt];
+                let new_nyt = self.add_node(None, old_nyt, nyt_number - 2);
+                let leaf = self.add_node(Some(symbol), old_nyt, nyt_number - 1);
+                self.nodes[old_nyt].children = Some((new_nyt, leaf));
+                self.nyt = new_nyt;
+                self.leaves[symbol as usize] = Some(leaf);
+                leaf
+            }
+        };
+
+        let mut node = Some(leaf);
+        while let Some(current) = node {
+            // Highest numbered node with the same weight
+            let weight = self.nodes[current].weight;
+            let mut leader = current;
+            for number in self.number[current] + 1..self.order.len() {
+                match self.order[number] {
+                    Some(other) if self.nodes[other].weight == weight => leader = other,
+                    _ => break,
+                }
+            }
+
+            if leader != current && Some(leader) != self.nodes[current].parent {
+                self.swap(current, leader);
+            }
+
+            self.nodes[current].weight += 1;
+            node = self.nodes[current].parent;
+        }
+    }
+
+    fn add_node(&mut self, symbol: Option<u16>, parent: usize, number: usize) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node { weight: 0, parent: Some(parent), children: None, symbol });
+        self.number.push(number);
+        self.order[number] = Some(id);
+        id
+    }
+
+    /// Swaps the positions of two nodes (and their subtrees) in the tree, as well as their numbers.
+    fn swap(&mut self, a: usize, b: usize) {
+        let parent_a = self.nodes[a].parent.unwrap();
+        let parent_b = self.nodes[b].parent.unwrap();
+
+        if parent_a == parent_b {
+            let (left, right) = self.nodes[parent_a].children.unwrap();
+            self.nodes[parent_a].children = Some((right, left));
+        } else {
+            self.replace_child(parent_a, a, b);
+            self.replace_child(parent_b, b, a);
+            self.nodes[a].parent = Some(parent_b);
+            self.nodes[b].parent = Some(parent_a);
+        }
+
+        self.number.swap(a, b);
+        self.order[self.number[a]] = Some(a);
+        self.order[self.number[b]] = Some(b);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let (left, right) = self.nodes[parent].children.unwrap();
+        self.nodes[parent].children = Some(if left == old { (new, right) } else { (left, new) });
+    }
+}
+
+impl Default for AdaptiveHuffman {
+    fn default() -> Self {
+        AdaptiveHuffman::new()
+    }
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Number of bytes of the stream an encoder holds before writing them to the inner writer.
+const STREAM_BUFFER_BYTES: usize = 1 << 16;
+
+
+/// Adaptive Huffman encoder wrapping a writer: the bytes written to it are encoded one by one and written to the inner
+/// writer as they are complete, with a fresh model. `finish` must be called to end the stream with the end-of-stream
+/// symbol.
+pub struct AdaptiveEncoder<W: Write> {
+    inner: W,
+    model: AdaptiveHuffman,
+    writer: BitWriter,
+}
+
+impl<W: Write> AdaptiveEncoder<W> {
+    pub fn new(inner: W) -> AdaptiveEncoder<W> {
+        AdaptiveEncoder { inner, model: AdaptiveHuffman::new(), writer: BitWriter::new() }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Ends the stream, writes its last bits (the last byte being padded with zeros) and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.model.encode_symbol(EOF_SYMBOL, &mut self.writer);
+        self.inner.write_all(&self.writer.into_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for AdaptiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.model.encode_symbol(byte as u16, &mut self.writer);
+        }
+        if self.writer.len() >= 8 * STREAM_BUFFER_BYTES as u64 {
+            self.inner.write_all(&self.writer.take_bytes())?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the whole bytes of the stream so far and flushes the inner writer. The bits of an unfinished byte can
+    /// only be written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.writer.take_bytes())?;
+        self.inner.flush()
+    }
+}
+
+
+/// Adaptive Huffman decoder wrapping a reader: the bytes read from it are the content of the stream written by an
+/// `AdaptiveEncoder` on the inner reader. The inner reader is read one byte at a time, when a symbol needs more bits,
+/// so it stops right after the end of the stream (a `BufReader` avoids small reads on files).
+pub struct AdaptiveDecoder<R: Read> {
+    inner: R,
+    model: AdaptiveHuffman,
+    /// Bytes read from the inner reader which still hold bits to decode, and how many bits of them were decoded
+    input: Vec<u8>,
+    bit_pos: usize,
+    finished: bool,
+}
+
+impl<R: Read> AdaptiveDecoder<R> {
+    pub fn new(inner: R) -> AdaptiveDecoder<R> {
+        AdaptiveDecoder { inner, model: AdaptiveHuffman::new(), input: Vec::new(), bit_pos: 0, finished: false }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the inner reader, positioned right after the end of the stream once everything was read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Decodes the next symbol, reading more bytes of the inner reader while the symbol goes past the ones read.
+    fn read_symbol(&mut self) -> Result<u16, Error> {
+        // Bytes already decoded are dropped
+        self.input.drain(..self.bit_pos / 8);
+        self.bit_pos %= 8;
+
+        loop {
+            let mut reader = BitReader::without_length(&self.input);
+            reader.skip(self.bit_pos);
+            // The model is only updated once the whole symbol was read, so it can be read again with more bits
+            match self.model.decode_symbol(&mut reader) {
+                Ok(symbol) => {
+                    self.bit_pos = self.input.len() * 8 - reader.remaining();
+                    return Ok(symbol);
+                },
+                Err(err) if reader.remaining() < RAW_SYMBOL_BITS => {
+                    let mut byte = [0];
+                    match self.inner.read_exact(&mut byte) {
+                        Ok(()) => self.input.push(byte[0]),
+                        Err(read_err) if read_err.kind() == io::ErrorKind::UnexpectedEof => return Err(err),
+                        Err(read_err) => return Err(read_err.into()),
+                    }
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for AdaptiveDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() && !self.finished {
+            match self.read_symbol()? {
+                EOF_SYMBOL => {
+                    // Only the zero padding of the last byte can follow the end of the stream
+                    let mut reader = BitReader::without_length(&self.input);
+                    reader.skip(self.bit_pos);
+                    if reader.read_int(reader.remaining())? != 0 {
+                        return Err(Error::corrupt("unexpected bits after the end of the adaptive Huffman stream").into());
+                    }
+                    self.finished = true;
+                },
+                byte => {
+                    buf[count] = byte as u8;
+                    count += 1;
+                },
+            }
+        }
+        Ok(count)
+    }
+}
+
+
+/// Encodes `content` in one pass with a fresh adaptive model, and ends the stream with the end-of-stream symbol.
+/// Nothing about the content has to be known before the first bit is written.
+pub fn encode_adaptive(content: &[u8]) -> Vec<u8> {
+    let mut encoder = AdaptiveEncoder::new(Vec::new());
+    encoder.write_all(content).expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+
+/// Decodes a stream written by `encode_adaptive`, which must take the whole of `payload`.
+pub fn decode_adaptive(payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoder = AdaptiveDecoder::new(payload);
+    let mut content = Vec::new();
+    decoder.read_to_end(&mut content)?;
+
+    if !decoder.into_inner().is_empty() {
+        return Err(Error::corrupt("unexpected data after the end of the adaptive Huffman stream"));
+    }
+    Ok(content)
+}
+
+
+/// Returns the archive of the files `files`, given as (name, content) pairs, using adaptive Huffman coding on bytes.
+/// Each file is encoded with its own model, so the method header is empty.
+pub fn encode_adaptive_huffman(files: &[NamedFile]) -> Archive {
+    let members = files.iter()
+        .map(|(name, content)| Member {
+            name: name.clone(),
+            original_len: content.len() as u64,
+            payload: encode_adaptive(content),
+        })
+        .collect();
+
+    Archive { method: EncodingMethod::AdaptiveHuffman, method_header: Vec::new(), members }
+}
+
+
+/// Decodes archives written by `encode_adaptive_huffman`, returning the content of each of their files.
+pub fn decode_adaptive_huffman(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let content = decode_adaptive(&member.payload)?;
+        if content.len() as u64 != member.original_len {
+            return Err(Error::corrupt(format!("{} doesn't have its original length", member.name)));
+        }
+        contents.push(content);
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{read_archive, write_archive};
+
+    #[test]
+    fn adaptive_empty_file_round_trips() {
+        let archive = Archive {
+            method: EncodingMethod::AdaptiveHuffman,
+            method_header: Vec::new(),
+            members: vec![Member { name: String::new(), original_len: 0, payload: encode_adaptive(b"") }],
+        };
+        let archive = read_archive(&write_archive(&archive).unwrap()).unwrap();
+        assert_eq!(decode_adaptive_huffman(&archive).unwrap(), vec![Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn streams_round_trip_in_chunks() {
+        // Skewed text longer than the encoder buffer, then every byte value
+        let text = "eeeeeeee tea at ten, ".repeat(4000);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        for content in [text.as_bytes(), &all_bytes[..]] {
+            let mut encoder = AdaptiveEncoder::new(Vec::new());
+            for chunk in content.chunks(1000) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(compressed, encode_adaptive(content));
+
+            let mut decoder = AdaptiveDecoder::new(&compressed[..]);
+            let mut decoded = Vec::new();
+            let mut chunk = [0; 777];
+            loop {
+                match decoder.read(&mut chunk).unwrap() {
+                    0 => break,
+                    count => decoded.extend_from_slice(&chunk[..count]),
+                }
+            }
+            assert_eq!(decoded, content);
+            assert!(decoder.into_inner().is_empty());
+        }
+        assert!(encode_adaptive(text.as_bytes()).len() < text.len() / 3);
+    }
+
+    #[test]
+    fn damaged_streams_are_corrupt() {
+        let corrupt = |result: Result<Vec<u8>, Error>| matches!(result, Err(Error::CorruptArchive { .. }));
+
+        let content = b"abracadabra, abracadabra";
+        let payload = encode_adaptive(content);
+        for len in 0..payload.len() {
+            assert!(corrupt(decode_adaptive(&payload[..len])), "{} bytes", len);
+        }
+        assert!(corrupt(decode_adaptive(&[payload.clone(), vec![0]].concat())));
+        // A first symbol past the end of the alphabet
+        assert!(corrupt(decode_adaptive(&[0xFF; 4])));
+
+        // A flipped bit gives another stream or an error, never a panic
+        for bit in 0..payload.len() * 8 {
+            let mut damaged = payload.clone();
+            damaged[bit / 8] ^= 0x80 >> (bit % 8);
+            let result = decode_adaptive(&damaged);
+            assert!(result.is_ok() || corrupt(result), "bit {}", bit);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::hash::Hash;
+
+use crate::{Config, EncodingMethod, Options};
+use crate::archive::{encode_archive, read_input_files, write_archive, NamedFile};
+use crate::error::Error;
+use crate::export::json_string;
+use crate::huffman::{build_canonical_bitvec_map_from_frequencies, byte_parser, encoded_bits_len, parser, CodeMap};
+
+
+// ---------- Report format -----------
+
+/// How the analysis is printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    /// One block of lines per file, for people
+    Text,
+    /// A JSON array with one object per file, for scripts and dashboards
+    Json,
+}
+
+impl ReportFormat {
+    /// Returns the format matching the name given with the `-a` option.
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "text" => Some(ReportFormat::Text),
+            "json" => Some(ReportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+
+// #################################
+// ------- Analysis ----------------
+// #################################
+
+/// What compressing a file would give, computed without writing anything.
+///
+/// Symbols are the characters of the file with the `huffman` method, and its bytes with every other method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileAnalysis {
+    pub name: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Number of symbols of the file, and number of different ones
+    pub symbols: u64,
+    pub distinct_symbols: usize,
+    /// Order-0 entropy, in bits per symbol: the least any code of single symbols (each one coded on its own, whatever
+    /// comes before it) can reach
+    pub entropy: f64,
+    /// Average length of the Huffman codes of the file, in bits per symbol, with the code length limit of the options
+    pub average_code_length: f64,
+    /// Bytes of the archive which aren't payload: the archive header and the method header (the shared table, the
+    /// dictionary...). Tables stored with the files (per-file tables) are part of their payload.
+    pub header_bytes: u64,
+    /// Size of an archive holding the file alone, with the options
+    pub predicted_size: u64,
+}
+
+
+/// Returns the order-0 entropy of symbols with these frequencies, in bits per symbol.
+pub fn entropy<S>(frequencies: &HashMap<S, u64>) -> f64 {
+    let total: u64 = frequencies.values().sum();
+    frequencies.values()
+        .map(|&count| count as f64 / total as f64)
+        .map(|p| -p * p.log2())
+        .sum()
+}
+
+
+/// Returns the (symbol count, entropy, average code length) of symbols with these frequencies.
+fn symbol_statistics<S: Copy + Ord + Hash>(frequencies: &HashMap<S, u64>, max_code_length: Option<usize>) -> Result<(u64, f64, f64), Error> {
+    let symbols: u64 = frequencies.values().sum();
+    if symbols == 0 {
+        return Ok((0, 0.0, 0.0));
+    }
+
+    let code_map: CodeMap<S> = build_canonical_bitvec_map_from_frequencies(frequencies, max_code_length)?;
+    Ok((symbols, entropy(frequencies), encoded_bits_len(frequencies, &code_map).expect("the map is built from these frequencies") as f64 / symbols as f64))
+}
+
+
+/// Analyzes a file for the encoding method and code length limit of `options`.
+pub fn analyze_file(name: &str, content: &[u8], options: &Options) -> Result<FileAnalysis, Error> {
+    let ((symbols, entropy, average_code_length), distinct_symbols) = if options.encoding == EncodingMethod::Huffman {
+        let text = str::from_utf8(content)
+            .map_err(|err| Error::InvalidArgument(format!("{} is not UTF-8 text ({}), use the huffman-bytes method for binary files.", name, err)))?;
+        let frequencies = parser(text)?;
+        (symbol_statistics(&frequencies, options.max_code_length)?, frequencies.len())
+    } else {
+        let frequencies = byte_parser(content);
+        (symbol_statistics(&frequencies, options.max_code_length)?, frequencies.len())
+    };
+
+    let file: NamedFile = (name.to_string(), content.to_vec());
+    let archive = encode_archive(&[file], options)?;
+    let predicted_size = write_archive(&archive)?.len() as u64;
+    let payload_bytes: u64 = archive.members.iter().map(|member| member.payload.len() as u64).sum();
+
+    Ok(FileAnalysis {
+        name: name.to_string(),
+        size: content.len() as u64,
+        symbols,
+        distinct_symbols,
+        entropy,
+        average_code_length,
+        header_bytes: predicted_size - payload_bytes,
+        predicted_size,
+    })
+}
+
+
+// #################################
+// ------- Reports -----------------
+// #################################
+
+/// Returns the analyses as text, one block of lines per file.
+pub fn text_report(analyses: &[FileAnalysis]) -> String {
+    let mut report = String::new();
+    for analysis in analyses {
+        let ratio = if analysis.size == 0 { 0.0 } else { 100.0 * analysis.predicted_size as f64 / analysis.size as f64 };
+        writeln!(report, "{}", analysis.name).unwrap();
+        writeln!(report, "  size:                {} bytes", analysis.size).unwrap();
+        writeln!(report, "  symbols:             {} ({} distinct)", analysis.symbols, analysis.distinct_symbols).unwrap();
+        writeln!(report, "  entropy:             {:.4} bits/symbol", analysis.entropy).unwrap();
+        writeln!(report, "  average code length: {:.4} bits/symbol", analysis.average_code_length).unwrap();
+        writeln!(report, "  header overhead:     {} bytes", analysis.header_bytes).unwrap();
+        writeln!(report, "  predicted size:      {} bytes ({:.1} % of the file)", analysis.predicted_size, ratio).unwrap();
+    }
+    report
+}
+
+
+/// Returns the analyses as a JSON array with one object per file, whose keys are the fields of `FileAnalysis`.
+pub fn json_report(analyses: &[FileAnalysis]) -> String {
+    let objects: Vec<String> = analyses.iter().map(|analysis| format!(
+        "  {{\"name\": {}, \"size\": {}, \"symbols\": {}, \"distinct_symbols\": {}, \"entropy\": {:.6}, \"average_code_length\": {:.6}, \"header_bytes\": {}, \"predicted_size\": {}}}",
+        json_string(&analysis.name), analysis.size, analysis.symbols, analysis.distinct_symbols, analysis.entropy,
+        analysis.average_code_length, analysis.header_bytes, analysis.predicted_size,
+    )).collect();
+
+    if objects.is_empty() { "[]\n".into() } else { format!("[\n{}\n]\n", objects.join(",\n")) }
+}
+
+
+/// Prints the analysis of every file given in the Config struct, with the options of the Config struct.
+/// Archives (`.zip` files) stand for the files they hold. Nothing is written.
+pub fn analyze_files(config: &Config, format: ReportFormat) -> Result<(), Error> {
+    let mut analyses = Vec::new();
+    for (name, content) in read_input_files(&config.files)? {
+        analyses.push(analyze_file(&name, &content, &config.options)?);
+    }
+
+    print!("{}", match format {
+        ReportFormat::Text => text_report(&analyses),
+        ReportFormat::Json => json_report(&analyses),
+    });
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analysis_matches_the_archive() {
+        // 4 symbols of probabilities 1/2, 1/4, 1/8, 1/8: the Huffman code reaches the entropy
+        let content = b"aaaabbcd".repeat(100);
+        let options = Options::with_encoding(EncodingMethod::HuffmanBytes);
+        let analysis = analyze_file("abcd", &content, &options).unwrap();
+
+        assert_eq!((analysis.size, analysis.symbols, analysis.distinct_symbols), (800, 800, 4));
+        assert_eq!(analysis.entropy, 1.75);
+        assert_eq!(analysis.average_code_length, 1.75);
+        let mut builder = crate::ArchiveBuilder::new(options);
+        builder.add_file("abcd", content);
+        assert_eq!(analysis.predicted_size, builder.build().unwrap().len() as u64);
+
+        let empty = analyze_file("empty", b"", &Options::default()).unwrap();
+        assert_eq!((empty.symbols, empty.entropy, empty.average_code_length), (0, 0.0, 0.0));
+        assert_eq!(json_report(&[empty]), "[\n  {\"name\": \"empty\", \"size\": 0, \"symbols\": 0, \"distinct_symbols\": 0, \"entropy\": 0.000000, \
+            \"average_code_length\": 0.000000, \"header_bytes\": 38, \"predicted_size\": 39}\n]\n");
+    }
+}
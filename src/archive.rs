@@ -0,0 +1,449 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{Config, EncodingMethod, Options};
+use crate::error::Error;
+use crate::adaptive::{decode_adaptive_huffman, encode_adaptive_huffman};
+use crate::bwt::{decode_bwt, encode_bwt};
+use crate::context::{decode_context_huffman, encode_context_huffman};
+use crate::deflate::{decode_deflate, encode_deflate};
+use crate::lz77::{decode_lz77, encode_lz77};
+use crate::lzw::{decode_lzw_archive, encode_lzw_archive};
+use crate::rle::{decode_rle, decode_rle_huffman, encode_rle, encode_rle_huffman};
+use crate::words::{decode_word_huffman, encode_word_huffman};
+use crate::huffman::{decode_bitvec_huffman, decode_bitvec_huffman_bytes, encode_bitvec_huffman, encode_bitvec_huffman_bytes, extract_file_from_path};
+
+
+// ---------- Format constants -----------
+
+/// First bytes of every archive, used to recognize the files written by this program.
+pub const MAGIC: [u8; 4] = *b"MRCA";
+
+/// Version of the archive layout. Must be increased every time the layout written by `write_archive`, or the layout
+/// of the method headers and payloads, changes.
+pub const FORMAT_VERSION: u8 = 10;
+
+
+// ---------- Archive structs -----------
+
+/// One file stored in an archive. `payload` holds the compressed content, whose layout depends on the encoding method.
+pub struct Member {
+    pub name: String,
+    pub original_len: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Parsed content of an archive.
+///
+/// `method_header` holds the data shared by every member (for example the Huffman code map) and is only read
+/// by the encoding method.
+pub struct Archive {
+    pub method: EncodingMethod,
+    pub method_header: Vec<u8>,
+    pub members: Vec<Member>,
+}
+
+
+/// File given to or taken from an archive: its name and its content.
+pub type NamedFile = (String, Vec<u8>);
+
+
+/// Keeps only the name of the file, without the leading path.
+pub fn file_name(path: &str) -> &str {
+    match path.rsplit_once('/')  {
+        None => path,
+        Some(n) => n.1,
+    }
+}
+
+
+/// Returns the name the member at `index` is extracted with. Names are stored without their path, but an archive from
+/// elsewhere must not write outside the folder, and members without a usable name (empty, `.` or `..`) get a
+/// placeholder name instead.
+pub fn extracted_name(member: &Member, index: usize) -> String {
+    match file_name(&member.name) {
+        "" | "." | ".." => format!("unnamed_{}", index + 1),
+        name => name.to_string(),
+    }
+}
+
+
+// #################################
+// ------- Writing -----------------
+// #################################
+
+/// Returns the bytes of an archive, with this layout (integers are little endian):
+///
+/// - `magic` (4 bytes), `format version` (u8), `method id` (u8), `file count` (u32)
+/// - for each member: `name length` (u16), `name` (UTF-8, possibly empty), `original length` (u64), `compressed length` (u64)
+/// - `method header length` (u32), then the method header
+/// - the payload of each member, one after the other
+pub fn write_archive(archive: &Archive) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.push(archive.method.id());
+    let file_count = u32::try_from(archive.members.len()).map_err(|_| Error::LimitExceeded("Too many files in the archive.".into()))?;
+    bytes.extend_from_slice(&file_count.to_le_bytes());
+
+    for member in &archive.members {
+        let name_len = u16::try_from(member.name.len()).map_err(|_| Error::LimitExceeded(format!("File name too long: {}", member.name)))?;
+        bytes.extend_from_slice(&name_len.to_le_bytes());
+        bytes.extend_from_slice(member.name.as_bytes());
+        bytes.extend_from_slice(&member.original_len.to_le_bytes());
+        bytes.extend_from_slice(&(member.payload.len() as u64).to_le_bytes());
+    }
+
+    let method_header_len = u32::try_from(archive.method_header.len()).map_err(|_| Error::LimitExceeded("Method header too long.".into()))?;
+    bytes.extend_from_slice(&method_header_len.to_le_bytes());
+    bytes.extend_from_slice(&archive.method_header);
+
+    for member in &archive.members {
+        bytes.extend_from_slice(&member.payload);
+    }
+
+    Ok(bytes)
+}
+
+
+/// Appends `value` to `bytes` as a variable-width integer (LEB128): 7 bits per byte, low bits first, the high bit of
+/// each byte telling if another byte follows. Small values only take one byte.
+pub fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+
+/// Number of bytes `value` takes once written by `write_varint`.
+pub fn varint_size(value: u64) -> usize {
+    (u64::BITS - value.leading_zeros()).max(1).div_ceil(7) as usize
+}
+
+
+// #################################
+// ------- Reading -----------------
+// #################################
+
+/// Cursor over the bytes of an archive, returning an error instead of panicking when the archive is too short.
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    /// All the bytes the reader goes through.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Current offset of the cursor in the bytes.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns the next `count` bytes and moves the cursor after them.
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let slice = self.pos.checked_add(count)
+            .and_then(|end| self.bytes.get(self.pos..end))
+            .ok_or(Error::corrupt_at(self.pos, "unexpected end of data"))?;
+        self.pos += count;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().expect("2 bytes taken")))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("4 bytes taken")))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("8 bytes taken")))
+    }
+
+    /// Reads an integer written by `write_varint`.
+    pub fn read_varint(&mut self) -> Result<u64, Error> {
+        read_varint_with(|| self.read_u8()).map_err(|err| match err {
+            Error::CorruptArchive { offset: None, reason } => Error::corrupt_at(self.pos, reason),
+            err => err,
+        })
+    }
+}
+
+
+/// Reads an integer written by `write_varint`, `next_byte` giving its bytes one by one. For streams which can't be read
+/// ahead, the error of an integer too long for a u64 has no offset.
+pub fn read_varint_with(mut next_byte: impl FnMut() -> Result<u8, Error>) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = next_byte()?;
+        // The tenth byte only holds the last bit of a u64
+        if shift == 63 && byte > 1 {
+            break;
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::corrupt("variable-width integer too long"))
+}
+
+
+/// Parses the bytes of an archive written by `write_archive`.
+/// Rejects files that don't start with `MAGIC` or that were written with another format version.
+pub fn read_archive(bytes: &[u8]) -> Result<Archive, Error> {
+    let mut reader = ByteReader::new(bytes);
+
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(Error::corrupt_at(0, "not an archive made by this program (wrong magic number)"));
+    }
+
+    let version = reader.read_u8()?;
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion { found: version, supported: FORMAT_VERSION });
+    }
+
+    let method_id = reader.read_u8()?;
+    let method = EncodingMethod::from_id(method_id)
+        .ok_or(Error::corrupt_at(reader.position() - 1, format!("unknown encoding method id {}", method_id)))?;
+
+    let file_count = reader.read_u32()? as usize;
+    let mut entries = Vec::new();
+    for _ in 0..file_count {
+        let name_len = reader.read_u16()? as usize;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())
+            .map_err(|_| Error::corrupt_at(reader.position() - name_len, "file name is not valid UTF-8"))?;
+        let original_len = reader.read_u64()?;
+        let compressed_len = reader.read_u64()?;
+        entries.push((name, original_len, compressed_len));
+    }
+
+    let method_header_len = reader.read_u32()? as usize;
+    let method_header = reader.take(method_header_len)?.to_vec();
+
+    let mut members = Vec::new();
+    for (name, original_len, compressed_len) in entries {
+        let compressed_len = usize::try_from(compressed_len)
+            .map_err(|_| Error::corrupt_at(reader.position(), "compressed length too large"))?;
+        let payload = reader.take(compressed_len)?.to_vec();
+        members.push(Member { name, original_len, payload });
+    }
+
+    if reader.position() != bytes.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the last file"));
+    }
+
+    Ok(Archive { method, method_header, members })
+}
+
+
+// #################################
+// ------- Encoding methods --------
+// #################################
+
+/// Returns the archive of the files `files`, given as (name, content) pairs, encoded with the method of `options`.
+pub fn encode_archive(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    Ok(match options.encoding {
+        EncodingMethod::Huffman => encode_bitvec_huffman(files, options)?,
+        EncodingMethod::HuffmanBytes => encode_bitvec_huffman_bytes(files, options)?,
+        EncodingMethod::AdaptiveHuffman => encode_adaptive_huffman(files),
+        EncodingMethod::Words => encode_word_huffman(files, options)?,
+        EncodingMethod::ContextHuffman => encode_context_huffman(files, options)?,
+        EncodingMethod::Lz77 => encode_lz77(files, options)?,
+        EncodingMethod::Deflate => encode_deflate(files, options)?,
+        EncodingMethod::Lzw => encode_lzw_archive(files, options)?,
+        EncodingMethod::Rle => encode_rle(files),
+        EncodingMethod::RleHuffman => encode_rle_huffman(files, options)?,
+        EncodingMethod::Bwt => encode_bwt(files, options)?,
+    })
+}
+
+
+/// Returns the content of each member of an archive, whatever encoding method it was written with.
+pub fn decode_archive(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    match archive.method {
+        EncodingMethod::Huffman => decode_bitvec_huffman(archive),
+        EncodingMethod::HuffmanBytes => decode_bitvec_huffman_bytes(archive),
+        EncodingMethod::AdaptiveHuffman => decode_adaptive_huffman(archive),
+        EncodingMethod::Words => decode_word_huffman(archive),
+        EncodingMethod::ContextHuffman => decode_context_huffman(archive),
+        EncodingMethod::Lz77 => decode_lz77(archive),
+        EncodingMethod::Deflate => decode_deflate(archive),
+        EncodingMethod::Lzw => decode_lzw_archive(archive),
+        EncodingMethod::Rle => decode_rle(archive),
+        EncodingMethod::RleHuffman => decode_rle_huffman(archive),
+        EncodingMethod::Bwt => decode_bwt(archive),
+    }
+}
+
+
+/// Builds an archive in memory from (name, content) pairs, without going through files.
+///
+/// ```
+/// use my_compressor::{ArchiveBuilder, Options};
+///
+/// let mut builder = ArchiveBuilder::new(Options::default());
+/// builder.add_file("hello.txt", "Hello world!").add_file("empty.txt", "");
+/// let archive = builder.build().unwrap();
+///
+/// let files = my_compressor::decompress_files(&archive).unwrap();
+/// assert_eq!(files[0], ("hello.txt".to_string(), b"Hello world!".to_vec()));
+/// ```
+pub struct ArchiveBuilder {
+    options: Options,
+    files: Vec<NamedFile>,
+}
+
+impl ArchiveBuilder {
+    pub fn new(options: Options) -> ArchiveBuilder {
+        ArchiveBuilder { options, files: Vec::new() }
+    }
+
+    /// Adds a file to the archive. The name is stored as given, it may be empty.
+    pub fn add_file(&mut self, name: impl Into<String>, content: impl Into<Vec<u8>>) -> &mut ArchiveBuilder {
+        self.files.push((name.into(), content.into()));
+        self
+    }
+
+    /// Encodes the files added so far and returns the bytes of the archive.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        write_archive(&encode_archive(&self.files, &self.options)?)
+    }
+}
+
+
+// #################################
+// ------- Files -------------------
+// #################################
+
+/// Compresses every file given in the Config struct into the archive named in it, with the options of the Config struct.
+pub fn compress_files(config: &Config) -> Result<(), Error> {
+    let mut builder = ArchiveBuilder::new(config.options);
+    for file in &config.files {
+        println!("Encoding {} in {}", file, &config.archive_name);
+        builder.add_file(file_name(file), fs::read(Path::new(file))?);
+    }
+
+    fs::write(Path::new(&config.archive_name), builder.build()?)?;
+    Ok(())
+}
+
+
+/// Reads the files at `paths` as (name, content) pairs, for the commands which look at files without archiving them.
+/// Archives (`.zip` files) stand for the files they hold.
+pub fn read_input_files(paths: &[String]) -> Result<Vec<NamedFile>, Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        let bytes = fs::read(Path::new(path))?;
+        if path.ends_with(".zip") {
+            let archive = read_archive(&bytes)?;
+            let contents = decode_archive(&archive)?;
+            files.extend(archive.members.iter().enumerate().map(|(index, member)| extracted_name(member, index)).zip(contents));
+        } else {
+            files.push((file_name(path).to_string(), bytes));
+        }
+    }
+    Ok(files)
+}
+
+
+/// Decompresses every archive given in the Config struct, whatever encoding method they were written with.
+/// Creates a subfolder for each archive, named after it, and writes its files in it.
+pub fn decompress_archives(config: &Config) -> Result<(), Error> {
+    for archive_path in &config.files {
+        let archive = read_archive(&fs::read(Path::new(archive_path))?)?;
+        let contents = decode_archive(&archive)?;
+
+        let archive_name = extract_file_from_path(archive_path)?;
+        if !Path::new(&archive_name).exists() {
+            fs::create_dir(&archive_name)?;
+        }
+
+        for (index, (member, content)) in archive.members.iter().zip(contents).enumerate() {
+            let name = extracted_name(member, index);
+            fs::write(format!("{}/{}", archive_name, name), content)?;
+            println!("Decompressed {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes of a small archive holding one file.
+    fn archive_bytes() -> Vec<u8> {
+        let files = vec![("a.txt".to_string(), b"abracadabra".to_vec())];
+        write_archive(&encode_archive(&files, &Options::with_encoding(EncodingMethod::HuffmanBytes)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn read_archive_checks_the_container() {
+        let bytes = archive_bytes();
+        assert_eq!(read_archive(&bytes).unwrap().members[0].original_len, 11);
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'P';
+        assert!(matches!(read_archive(&bad_magic), Err(Error::CorruptArchive { offset: Some(0), .. })));
+
+        let mut other_version = bytes.clone();
+        other_version[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            read_archive(&other_version),
+            Err(Error::UnsupportedVersion { found, supported: FORMAT_VERSION }) if found == FORMAT_VERSION + 1
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(read_archive(&trailing), Err(Error::CorruptArchive { offset: Some(offset), .. }) if offset == bytes.len() as u64));
+    }
+
+    #[test]
+    fn varint_size_matches_write_varint() {
+        for value in [0, 1, 127, 128, 16_383, 16_384, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(varint_size(value), bytes.len(), "{}", value);
+            assert_eq!(ByteReader::new(&bytes).read_varint().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn varints_too_long_for_a_u64_are_rejected() {
+        let mut too_long = vec![0xFF; 9];
+        too_long.push(0x02);
+        assert!(matches!(ByteReader::new(&too_long).read_varint(), Err(Error::CorruptArchive { offset: Some(10), .. })));
+        assert!(matches!(ByteReader::new(&[0x80; 11]).read_varint(), Err(Error::CorruptArchive { offset: Some(10), .. })));
+        assert!(matches!(ByteReader::new(&[0x80; 3]).read_varint(), Err(Error::CorruptArchive { offset: Some(3), .. })));
+
+        // Same checks when the bytes come one by one
+        let mut bytes = too_long.iter();
+        assert!(read_varint_with(|| bytes.next().copied().ok_or(Error::corrupt("end"))).is_err());
+    }
+
+    #[test]
+    fn unnamed_members_get_placeholder_names() {
+        let member = |name: &str| Member { name: name.to_string(), original_len: 0, payload: Vec::new() };
+        assert_eq!(extracted_name(&member(""), 0), "unnamed_1");
+        assert_eq!(extracted_name(&member(".."), 2), "unnamed_3");
+        assert_eq!(extracted_name(&member("dir/"), 0), "unnamed_1");
+        assert_eq!(extracted_name(&member("dir/a.txt"), 0), "a.txt");
+    }
+}
@@ -0,0 +1,186 @@
+use bitvec::prelude::*;
+
+use crate::archive::ByteReader;
+use crate::error::Error;
+
+
+// #################################
+// ------- Writer ------------------
+// #################################
+
+/// Bit-level writer which keeps track of the exact number of bits written, so the padding of the last byte
+/// can never be mistaken for data.
+pub struct BitWriter {
+    bits: BitVec<u8, Msb0>,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter { bits: BitVec::new() }
+    }
+
+    /// Appends a code (or any sequence of bits) to the stream.
+    pub fn write_bits(&mut self, bits: &BitSlice<u8, Msb0>) {
+        self.bits.extend_from_bitslice(bits);
+    }
+
+    /// Appends the `count` low bits of `value`, most significant first.
+    pub fn write_int(&mut self, value: u64, count: usize) {
+        for i in (0..count).rev() {
+            self.bits.push((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Number of bits written so far.
+    pub fn len(&self) -> u64 {
+        self.bits.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Returns the stream as bytes, prefixed by its exact length in bits:
+    /// `bit length` (u64, little endian), then the bits, the last byte being padded with zeros.
+    pub fn finish(self) -> Vec<u8> {
+        let mut bytes = self.len().to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.bits.into_vec());
+        bytes
+    }
+
+    /// Removes and returns the whole bytes written so far, keeping the bits of an unfinished last byte, so a
+    /// self-terminated stream can be written out as it goes. `len` then only counts the bits still held.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        let rest = self.bits.split_off(self.bits.len() / 8 * 8);
+        std::mem::replace(&mut self.bits, rest).into_vec()
+    }
+
+    /// Returns the stream as bytes without its length, for self-terminated streams. The last byte is padded with zeros.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bits.into_vec()
+    }
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        BitWriter::new()
+    }
+}
+
+
+// #################################
+// ------- Reader ------------------
+// #################################
+
+/// Bit-level reader over a stream written by `BitWriter::finish`. It never reads past the recorded bit length.
+pub struct BitReader<'a> {
+    bits: &'a BitSlice<u8, Msb0>,
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads the bit length at the start of `bytes` and checks that the stream holds exactly that many bits.
+    pub fn new(bytes: &'a [u8]) -> Result<BitReader<'a>, Error> {
+        let (len_bytes, data) = bytes.split_first_chunk::<8>()
+            .ok_or(Error::corrupt_at(0, "bitstream is missing its length"))?;
+        let bit_len = usize::try_from(u64::from_le_bytes(*len_bytes))
+            .map_err(|_| Error::LimitExceeded("Bitstream too long for this platform.".into()))?;
+
+        if data.len() < bit_len.div_ceil(8) {
+            return Err(Error::corrupt_at(8, format!("bitstream truncated ({} bits announced, {} available)", bit_len, data.len() * 8)));
+        }
+        if data.len() > bit_len.div_ceil(8) {
+            return Err(Error::corrupt_at(8 + bit_len.div_ceil(8), "unexpected bytes after the end of the bitstream"));
+        }
+
+        Ok(BitReader { bits: &data.view_bits::<Msb0>()[..bit_len], pos: 0 })
+    }
+
+    /// Reads a stream written by `BitWriter::into_bytes`, which has no recorded length: every bit of `bytes` is readable.
+    pub fn without_length(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bits: bytes.view_bits::<Msb0>(), pos: 0 }
+    }
+
+    /// Returns the next bit, or an error if the stream has no bit left.
+    pub fn read_bit(&mut self) -> Result<bool, Error> {
+        let bit = *self.bits.get(self.pos).ok_or(Error::corrupt("bitstream truncated"))?;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    /// Returns the next `count` bits (at most 64) as an integer, without moving the cursor, along with the number of
+    /// bits actually available. When fewer than `count` bits are left, the missing low bits are zeros.
+    pub fn peek_bits(&self, count: usize) -> (u64, usize) {
+        let available = count.min(self.remaining());
+        if available == 0 {
+            return (0, 0);
+        }
+        let value: u64 = self.bits[self.pos..self.pos + available].load_be();
+        (value << (count - available), available)
+    }
+
+    /// Reads `count` bits (at most 64) as an integer, most significant first.
+    pub fn read_int(&mut self, count: usize) -> Result<u64, Error> {
+        let (value, available) = self.peek_bits(count);
+        if available < count {
+            return Err(Error::corrupt("bitstream truncated"));
+        }
+        self.skip(count);
+        Ok(value)
+    }
+
+    /// Moves the cursor `count` bits forward. `count` must not exceed `remaining()`.
+    pub fn skip(&mut self, count: usize) {
+        debug_assert!(count <= self.remaining());
+        self.pos += count;
+    }
+
+    /// Number of bits left to read.
+    pub fn remaining(&self) -> usize {
+        self.bits.len() - self.pos
+    }
+}
+
+
+/// Returns the bytes of the stream written by `BitWriter::finish` at the position of `reader`, length included,
+/// and moves the reader after it. Used when other data follows the stream.
+pub fn take_bitstream<'a>(reader: &mut ByteReader<'a>) -> Result<&'a [u8], Error> {
+    let start = reader.position();
+    let bit_len = reader.read_u64()?;
+    let byte_len = usize::try_from(bit_len.div_ceil(8))
+        .map_err(|_| Error::corrupt_at(start, "bitstream length too large"))?;
+    reader.take(byte_len)?;
+    Ok(&reader.bytes()[start..reader.position()])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_bitstreams_report_where_the_bits_start() {
+        let mut writer = BitWriter::new();
+        writer.write_int(0xABC, 12);
+        let stream = writer.finish();
+        let mut reader = BitReader::new(&stream).unwrap();
+        assert_eq!(reader.read_int(12).unwrap(), 0xABC);
+        assert!(reader.read_bit().is_err());
+
+        // A stream cut in the middle of its bits, after another stream
+        let bytes = [&stream[..], &stream[..stream.len() - 1]].concat();
+        let mut reader = ByteReader::new(&bytes);
+        assert_eq!(take_bitstream(&mut reader).unwrap(), &stream[..]);
+        assert!(matches!(
+            take_bitstream(&mut reader),
+            Err(Error::CorruptArchive { offset: Some(offset), .. }) if offset == stream.len() as u64 + 8
+        ));
+
+        assert!(matches!(BitReader::new(&stream[..stream.len() - 1]), Err(Error::CorruptArchive { offset: Some(8), .. })));
+        assert!(matches!(BitReader::new(&stream[..5]), Err(Error::CorruptArchive { offset: Some(0), .. })));
+        assert!(matches!(
+            BitReader::new(&[&stream[..], &[0]].concat()),
+            Err(Error::CorruptArchive { offset: Some(offset), .. }) if offset == stream.len() as u64
+        ));
+    }
+}
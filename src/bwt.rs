@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use crate::{EncodingMethod, Options};
+use crate::archive::{varint_size, write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::bitstream::{take_bitstream, BitReader, BitWriter};
+use crate::error::Error;
+use crate::huffman::{build_canonical_bitvec_map_from_frequencies, nibble_code_length, read_alphabet_lengths, write_alphabet_lengths, CanonicalDecoder, CodeMap};
+
+
+// ---------- Parameters -----------
+
+/// Default number of bytes sorted together, as `bzip2 -9`. Larger blocks find more context, but take more memory.
+pub const DEFAULT_BLOCK_SIZE: usize = 900_000;
+
+/// Largest block allowed, so the decoder never has to keep more than that in memory.
+pub const MAX_BLOCK_SIZE: usize = 1 << 24;
+
+/// Symbols coded with the same table, the table being chosen for each group (as in bzip2).
+const GROUP_SIZE: usize = 50;
+
+/// Most tables a block can have.
+const MAX_TABLES: usize = 6;
+
+/// Number of times the groups are spread between the tables and the tables rebuilt from their groups.
+const REFINING_PASSES: usize = 4;
+
+
+// ---------- Zero-run alphabet -----------
+// Runs of zeros of the move-to-front output are written as their length in bijective base 2, with the digits RUNA
+// (1) and RUNB (2), least significant first. The other values v (1 to 255) are the symbols v + 1.
+
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+const ALPHABET_SIZE: usize = 257;
+
+
+// #################################
+// ------- Block sorting -----------
+// #################################
+
+/// Returns the suffix array of `text`: the start of each suffix, in increasing order of the suffixes, a suffix coming
+/// before the longer suffixes it starts.
+///
+/// Built by prefix doubling: the suffixes are sorted by their first `k` bytes, then by their first `2k` bytes using
+/// the ranks of the first sort, until every suffix has its own rank.
+pub fn suffix_array(text: &[u8]) -> Vec<usize> {
+    let n = text.len();
+    let mut suffixes: Vec<usize> = (0..n).collect();
+    let mut rank: Vec<usize> = text.iter().map(|&byte| byte as usize).collect();
+    let mut new_rank = vec![0; n];
+
+    if n < 2 {
+        return suffixes;
+    }
+
+    let mut k = 1;
+    loop {
+        // Suffixes shorter than `k` bytes come first, as if followed by a byte smaller than all others
+        let key = |start: usize| (rank[start], rank.get(start + k).map_or(0, |&rank| rank + 1));
+        suffixes.sort_unstable_by_key(|&start| key(start));
+
+        new_rank[suffixes[0]] = 0;
+        for pair in suffixes.windows(2) {
+            new_rank[pair[1]] = new_rank[pair[0]] + (key(pair[0]) != key(pair[1])) as usize;
+        }
+        std::mem::swap(&mut rank, &mut new_rank);
+
+        if rank[suffixes[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+
+    suffixes
+}
+
+
+/// Returns the Burrows–Wheeler transform of `block` and its primary index.
+///
+/// The rotations of `block` followed by an end marker, smaller than every byte, are sorted: the transform is the last
+/// symbol of each rotation, and the primary index is the row whose last symbol is the marker, which is left out of the
+/// transform. The first row starts with the marker, so the primary index is never 0.
+pub fn bwt(block: &[u8]) -> (Vec<u8>, usize) {
+    let mut last_column = Vec::with_capacity(block.len());
+    let mut primary_index = 0;
+    let Some(&last) = block.last() else { return (last_column, primary_index) };
+
+    last_column.push(last);
+    for (row, start) in suffix_array(block).into_iter().enumerate() {
+        match start {
+            0 => primary_index = row + 1,
+            start => last_column.push(block[start - 1]),
+        }
+    }
+    (last_column, primary_index)
+}
+
+
+/// Returns the block whose transform is `last_column` with the primary index `primary_index` (see `bwt`).
+pub fn inverse_bwt(last_column: &[u8], primary_index: usize) -> Result<Vec<u8>, Error> {
+    let n = last_column.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if !(1..=n).contains(&primary_index) {
+        return Err(Error::corrupt(format!("primary index {} in a block of {} bytes", primary_index, n)));
+    }
+
+    // The first row of each byte in the sorted rotations, after the row of the end marker
+    let mut counts = [0; 256];
+    for &byte in last_column {
+        counts[byte as usize] += 1;
+    }
+    let mut first_row = [0; 256];
+    let mut row = 1;
+    for (first, count) in first_row.iter_mut().zip(counts) {
+        *first = row;
+        row += count;
+    }
+
+    // Row of the rotation starting with the last symbol of each row, skipping the row of the end marker
+    let mut previous_row = vec![0; n + 1];
+    for (index, &byte) in last_column.iter().enumerate() {
+        let row = if index < primary_index { index } else { index + 1 };
+        previous_row[row] = first_row[byte as usize];
+        first_row[byte as usize] += 1;
+    }
+
+    let mut block = vec![0; n];
+    let mut row = 0;
+    for pos in (0..n).rev() {
+        if row == primary_index {
+            return Err(Error::corrupt("block ends before its primary index"));
+        }
+        block[pos] = last_column[if row < primary_index { row } else { row - 1 }];
+        row = previous_row[row];
+    }
+    Ok(block)
+}
+
+
+// #################################
+// ------- Move-to-front -----------
+// #################################
+
+/// Replaces each byte by its position in a list of the 256 byte values, and moves it to the front of the list: runs
+/// of the same byte become runs of zeros, and frequent bytes small values.
+pub fn move_to_front(bytes: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+    bytes.iter().map(|&byte| {
+        let pos = list.iter().position(|&other| other == byte).expect("every byte is in the list");
+        list.copy_within(0..pos, 1);
+        list[0] = byte;
+        pos as u8
+    }).collect()
+}
+
+
+/// Reverses `move_to_front`.
+pub fn inverse_move_to_front(positions: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+    positions.iter().map(|&pos| {
+        let byte = list[pos as usize];
+        list.copy_within(0..pos as usize, 1);
+        list[0] = byte;
+        byte
+    }).collect()
+}
+
+
+/// Returns the symbols of the move-to-front output `positions` (see the zero-run alphabet).
+fn zero_run_symbols(positions: &[u8]) -> Vec<u16> {
+    let mut symbols = Vec::new();
+    let mut run = 0;
+    for &pos in positions.iter().chain([1].iter()) {
+        if pos == 0 {
+            run += 1;
+            continue;
+        }
+
+        while run > 0 {
+            symbols.push(if run % 2 == 1 { RUNA } else { RUNB });
+            run = (run - 1) / 2;
+        }
+        symbols.push(pos as u16 + 1);
+    }
+
+    // The value chained to flush the last run
+    symbols.pop();
+    symbols
+}
+
+
+// #################################
+// ------- Multi-table Huffman -----
+// #################################
+
+/// Code tables of a block, and the table coding each group of `GROUP_SIZE` symbols.
+struct TablePlan {
+    tables: Vec<CodeMap<u16>>,
+    selectors: Vec<usize>,
+}
+
+impl TablePlan {
+    /// Number of bits of the tables, selectors and codes of `symbols`.
+    fn bits(&self, symbols: &[u16]) -> usize {
+        let tables: usize = self.tables.iter().map(|table| varint_size(symbol_count(table) as u64) + symbol_count(table).div_ceil(2)).sum();
+        let selectors = self.selectors.len() * selector_bits(self.tables.len());
+        let codes: usize = symbols.chunks(GROUP_SIZE).zip(&self.selectors)
+            .map(|(group, &selector)| group.iter().map(|symbol| self.tables[selector][symbol].len()).sum::<usize>())
+            .sum();
+        8 * (1 + tables) + selectors + codes
+    }
+}
+
+
+/// Number of code lengths stored for a table: up to its last symbol (see `write_alphabet_lengths`).
+fn symbol_count(table: &CodeMap<u16>) -> usize {
+    table.keys().max().map_or(0, |&last| last as usize + 1)
+}
+
+
+/// Number of bits of each selector, for `table_count` tables.
+fn selector_bits(table_count: usize) -> usize {
+    (usize::BITS - (table_count - 1).leading_zeros()) as usize
+}
+
+
+/// Spreads the groups of `symbols` between `table_count` tables, with codes of at most `max_code_length` bits.
+///
+/// As in bzip2, each table first favours a range of symbols holding about the same share of the block, then each group
+/// goes to the table coding it in the fewest bits and the tables are rebuilt from the frequencies of their groups, a
+/// few times over. Every table has a code for every symbol of the block, so any of them can code any group.
+fn plan_tables(symbols: &[u16], table_count: usize, max_code_length: usize) -> Result<TablePlan, Error> {
+    let mut frequencies: HashMap<u16, u64> = HashMap::new();
+    for &symbol in symbols {
+        *frequencies.entry(symbol).or_insert(0) += 1;
+    }
+    let mut used: Vec<u16> = frequencies.keys().copied().collect();
+    used.sort_unstable();
+
+    // Initial costs: 0 bits for the symbols of the range of the table, the longest code for the others
+    let mut costs: Vec<HashMap<u16, usize>> = Vec::new();
+    let mut remaining = symbols.len() as u64;
+    let mut next = 0;
+    for table in 0..table_count {
+        let share = remaining / (table_count - table) as u64;
+        let mut taken = 0;
+        let mut cost: HashMap<u16, usize> = used.iter().map(|&symbol| (symbol, max_code_length)).collect();
+        while next < used.len() && (taken < share || table == table_count - 1) {
+            taken += frequencies[&used[next]];
+            cost.insert(used[next], 0);
+            next += 1;
+        }
+        remaining -= taken;
+        costs.push(cost);
+    }
+
+    let mut plan = TablePlan { tables: Vec::new(), selectors: Vec::new() };
+    for _ in 0..REFINING_PASSES {
+        let mut table_frequencies: Vec<HashMap<u16, u64>> = vec![used.iter().map(|&symbol| (symbol, 1)).collect(); table_count];
+        plan.selectors = symbols.chunks(GROUP_SIZE).map(|group| {
+            let selector = (0..table_count)
+                .min_by_key(|&table| group.iter().map(|symbol| costs[table][symbol]).sum::<usize>())
+                .expect("at least one table");
+            for &symbol in group {
+                *table_frequencies[selector].get_mut(&symbol).expect("every symbol has a frequency") += 1;
+            }
+            selector
+        }).collect();
+
+        plan.tables = table_frequencies.iter()
+            .map(|frequencies| build_canonical_bitvec_map_from_frequencies(frequencies, Some(max_code_length)))
+            .collect::<Result<_, _>>()?;
+        costs = plan.tables.iter()
+            .map(|table| table.iter().map(|(&symbol, code)| (symbol, code.len())).collect())
+            .collect();
+    }
+
+    // A group always goes to its best table, so the last selectors are kept
+    Ok(plan)
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Appends the coded block to `bytes`: `primary index` (varint), `table count` (u8), the code lengths of each table (see
+/// `write_alphabet_lengths`), then the bitstream (see `BitWriter::finish`) of each group of `GROUP_SIZE` symbols, as
+/// the index of its table (the fewest bits that can hold every table) followed by the codes of its symbols. No code is
+/// longer than `max_code_length` bits.
+fn encode_block(bytes: &mut Vec<u8>, block: &[u8], max_code_length: usize) -> Result<(), Error> {
+    let (last_column, primary_index) = bwt(block);
+    let symbols = zero_run_symbols(&move_to_front(&last_column));
+
+    // Every table count worth trying is tried, and the smallest block kept
+    let mut best: Option<(usize, TablePlan)> = None;
+    for table_count in 1..=MAX_TABLES.min(symbols.len().div_ceil(GROUP_SIZE)) {
+        let plan = plan_tables(&symbols, table_count, max_code_length)?;
+        let bits = plan.bits(&symbols);
+        if best.as_ref().is_none_or(|(best_bits, _)| bits < *best_bits) {
+            best = Some((bits, plan));
+        }
+    }
+    let (_, plan) = best.expect("blocks are never empty");
+
+    write_varint(bytes, primary_index as u64);
+    bytes.push(plan.tables.len() as u8);
+    for table in &plan.tables {
+        write_alphabet_lengths(bytes, table);
+    }
+
+    let mut encoded = BitWriter::new();
+    for (group, &selector) in symbols.chunks(GROUP_SIZE).zip(&plan.selectors) {
+        encoded.write_int(selector as u64, selector_bits(plan.tables.len()));
+        for symbol in group {
+            encoded.write_bits(&plan.tables[selector][symbol]);
+        }
+    }
+    bytes.extend_from_slice(&encoded.finish());
+    Ok(())
+}
+
+
+/// Reads a block of `block_len` bytes written by `encode_block`.
+fn decode_block(reader: &mut ByteReader, block_len: usize) -> Result<Vec<u8>, Error> {
+    let start = reader.position();
+    let primary_index = usize::try_from(reader.read_varint()?)
+        .map_err(|_| Error::corrupt_at(start, "primary index too large"))?;
+    let table_count = reader.read_u8()? as usize;
+    if !(1..=MAX_TABLES).contains(&table_count) {
+        return Err(Error::corrupt_at(reader.position() - 1, format!("block with {} code tables", table_count)));
+    }
+
+    let mut decoders = Vec::new();
+    for _ in 0..table_count {
+        decoders.push(CanonicalDecoder::from_lengths(&read_alphabet_lengths(reader, ALPHABET_SIZE)?)?);
+    }
+
+    let stream_start = reader.position();
+    let mut bits = BitReader::new(take_bitstream(reader)?)?;
+
+    // Zero runs are written out as their digits come, each digit being worth twice the one before
+    let mut positions: Vec<u8> = Vec::with_capacity(block_len);
+    let mut run_weight = 1;
+    'groups: while positions.len() < block_len {
+        let selector = bits.read_int(selector_bits(table_count))? as usize;
+        let decoder = decoders.get(selector).ok_or(Error::corrupt(format!("selector {} for {} tables", selector, table_count)))?;
+
+        for _ in 0..GROUP_SIZE {
+            match decoder.decode_symbol(&mut bits)? {
+                digit @ (RUNA | RUNB) => {
+                    positions.resize(positions.len() + run_weight * (digit as usize + 1), 0);
+                    run_weight *= 2;
+                },
+                symbol => {
+                    positions.push((symbol - 1) as u8);
+                    run_weight = 1;
+                },
+            }
+
+            if positions.len() > block_len {
+                return Err(Error::corrupt("symbols go past the end of the block"));
+            }
+            if positions.len() == block_len {
+                break 'groups;
+            }
+        }
+    }
+    if bits.remaining() != 0 {
+        return Err(Error::corrupt_at(stream_start, format!("{} bits left after the end of the block", bits.remaining())));
+    }
+
+    inverse_bwt(&inverse_move_to_front(&positions), primary_index)
+}
+
+
+/// Returns the archive of the files `files`, cut into blocks of the block size of `options`, each block being sorted
+/// by the Burrows–Wheeler transform, then move-to-front and zero-run coded, and Huffman coded with several tables, as
+/// in bzip2. The code length limit of `options` is capped to 15 bits, as the code lengths are stored as nibbles.
+///
+/// The method header holds the `block size` (varint). The payload of each file is its blocks one after the other (see
+/// `encode_block`), every block but the last being of the block size.
+pub fn encode_bwt(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    if !(1..=MAX_BLOCK_SIZE).contains(&options.block_size) {
+        return Err(Error::InvalidArgument(format!("The block size must be between 1 and {} bytes.", MAX_BLOCK_SIZE)));
+    }
+
+    let max_code_length = nibble_code_length(options.max_code_length).expect("the limit is always capped");
+    let mut method_header = Vec::new();
+    write_varint(&mut method_header, options.block_size as u64);
+
+    let mut members = Vec::new();
+    for (name, content) in files {
+        let mut payload = Vec::new();
+        for block in content.chunks(options.block_size) {
+            encode_block(&mut payload, block, max_code_length)?;
+        }
+        members.push(Member { name: name.clone(), original_len: content.len() as u64, payload });
+    }
+
+    Ok(Archive { method: EncodingMethod::Bwt, method_header, members })
+}
+
+
+/// Decodes archives written by `encode_bwt`, returning the content of each of their files.
+pub fn decode_bwt(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let block_size = reader.read_varint()? as usize;
+    if !(1..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(Error::corrupt_at(0, format!("blocks of {} bytes", block_size)));
+    }
+    if reader.position() != archive.method_header.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the block size"));
+    }
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let mut reader = ByteReader::new(&member.payload);
+        let mut content: Vec<u8> = Vec::new();
+        while (content.len() as u64) < member.original_len {
+            let block_len = (member.original_len - content.len() as u64).min(block_size as u64) as usize;
+            content.extend(decode_block(&mut reader, block_len)?);
+        }
+
+        if reader.position() != member.payload.len() {
+            return Err(Error::corrupt_at(reader.position(), "unexpected data after the last block"));
+        }
+        contents.push(content);
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banana_round_trips() {
+        // Rotations of "banana$": $banana, a$banan, ana$ban, anana$b, banana$, na$bana, nana$ba
+        assert_eq!(suffix_array(b"banana"), vec![5, 3, 1, 0, 4, 2]);
+        assert_eq!(bwt(b"banana"), (b"annbaa".to_vec(), 4));
+        assert_eq!(inverse_bwt(b"annbaa", 4).unwrap(), b"banana");
+        assert!(inverse_bwt(b"annbaa", 0).is_err());
+
+        assert_eq!(move_to_front(b"aaabbba"), vec![97, 0, 0, 98, 0, 0, 1]);
+        assert_eq!(inverse_move_to_front(&[97, 0, 0, 98, 0, 0, 1]), b"aaabbba");
+
+        // Runs of 1 to 4 zeros: A, B, AA, BA
+        assert_eq!(zero_run_symbols(&[0, 5, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0, 0]), vec![RUNA, 6, RUNB, 6, RUNA, RUNA, 6, RUNB, RUNA]);
+    }
+
+    #[test]
+    fn blocks_round_trip_with_several_tables() {
+        // Text then binary data, which favours different tables
+        let content: Vec<u8> = [b"the quick brown fox jumps over the lazy dog. ".repeat(20), (0..1000u32).map(|i| (i * i % 251) as u8).collect()].concat();
+        let files = vec![("mixed".to_string(), content.clone()), ("empty".to_string(), Vec::new())];
+        for block_size in [1, 500, DEFAULT_BLOCK_SIZE] {
+            let options = Options { block_size, ..Options::with_encoding(EncodingMethod::Bwt) };
+            let archive = encode_bwt(&files, &options).unwrap();
+            assert_eq!(decode_bwt(&archive).unwrap(), vec![content.clone(), Vec::new()]);
+        }
+    }
+
+    #[test]
+    fn code_length_limit_applies_to_every_table() {
+        let content = b"abracadabra, alakazam! ".repeat(40);
+        let mut bytes = Vec::new();
+        encode_block(&mut bytes, &content, 5).unwrap();
+
+        let mut reader = ByteReader::new(&bytes);
+        reader.read_varint().unwrap();
+        for _ in 0..reader.read_u8().unwrap() {
+            assert!(read_alphabet_lengths(&mut reader, ALPHABET_SIZE).unwrap().iter().all(|&(_, len)| len <= 5));
+        }
+        assert_eq!(decode_block(&mut ByteReader::new(&bytes), content.len()).unwrap(), content);
+
+        let options = Options { max_code_length: Some(5), ..Options::with_encoding(EncodingMethod::Bwt) };
+        let files = vec![("magic.txt".to_string(), content)];
+        assert_eq!(decode_bwt(&encode_bwt(&files, &options).unwrap()).unwrap()[0], files[0].1);
+    }
+}
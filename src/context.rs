@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::{EncodingMethod, Options};
+use crate::archive::{varint_size, write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::bitstream::{BitReader, BitWriter};
+use crate::error::Error;
+use crate::huffman::{build_canonical_bitvec_map_from_frequencies, nibble_code_length, read_nibble_lengths, sorted_code_lengths, write_nibble_lengths, CanonicalDecoder, CodeMap};
+
+
+// ---------- Contexts -----------
+
+/// Number of contexts: the value of the byte before the one being coded. The first byte of a file follows a 0.
+const CONTEXTS: usize = 256;
+
+/// Tables with more symbols than that are stored as 256 nibbles, the others as a list of symbols (see `write_table`).
+const MAX_SPARSE_SYMBOLS: usize = 85;
+
+/// Number of times the contexts are shared out between their own table and the fallback table (see `plan_tables`).
+const PLANNING_PASSES: usize = 3;
+
+
+/// Returns the number of times each byte follows each context in `files`.
+fn context_frequencies(files: &[NamedFile]) -> Vec<HashMap<u8, u64>> {
+    let mut frequencies = vec![HashMap::new(); CONTEXTS];
+    for (_, content) in files {
+        let mut previous = 0;
+        for &byte in content {
+            *frequencies[previous as usize].entry(byte).or_insert(0) += 1;
+            previous = byte;
+        }
+    }
+    frequencies
+}
+
+
+/// Number of bits of symbols with these frequencies coded with `code_map`, `None` if a symbol has no code in it.
+fn coded_bits(frequencies: &HashMap<u8, u64>, code_map: &CodeMap<u8>) -> Option<u64> {
+    frequencies.iter().map(|(symbol, &count)| code_map.get(symbol).map(|code| count * code.len() as u64)).sum()
+}
+
+
+// #################################
+// ------- Tables ------------------
+// #################################
+
+/// Size in bytes of a table of `symbol_count` symbols written by `write_table`, without its symbol count.
+fn table_size(symbol_count: usize) -> usize {
+    if symbol_count > MAX_SPARSE_SYMBOLS { CONTEXTS / 2 } else { symbol_count + symbol_count.div_ceil(2) }
+}
+
+
+/// Appends the code lengths of a table to `bytes`: `symbol count` (varint), then either
+/// - sparse tables (at most `MAX_SPARSE_SYMBOLS` symbols): the symbols (u8 each, in increasing order), then their
+///   code lengths (see `write_nibble_lengths`)
+/// - dense tables: the code length of each of the 256 byte values, 0 for the bytes absent from the table
+///
+/// The form used is the smallest one for the symbol count, so the reader finds it from the count.
+fn write_table(bytes: &mut Vec<u8>, code_map: &CodeMap<u8>) {
+    let mut lengths = sorted_code_lengths(code_map);
+    lengths.sort_unstable();
+    write_varint(bytes, lengths.len() as u64);
+
+    let nibbles: Vec<usize> = if lengths.len() > MAX_SPARSE_SYMBOLS {
+        let mut dense = vec![0; CONTEXTS];
+        for &(symbol, len) in &lengths {
+            dense[symbol as usize] = len;
+        }
+        dense
+    } else {
+        bytes.extend(lengths.iter().map(|&(symbol, _)| symbol));
+        lengths.iter().map(|&(_, len)| len).collect()
+    };
+
+    write_nibble_lengths(bytes, &nibbles);
+}
+
+
+/// Reads the (byte, code length) pairs of a table written by `write_table`.
+fn read_table(reader: &mut ByteReader) -> Result<Vec<(u8, usize)>, Error> {
+    let start = reader.position();
+    let symbol_count = reader.read_varint()? as usize;
+    if symbol_count > CONTEXTS {
+        return Err(Error::corrupt_at(start, format!("table of {} symbols, bytes only have 256 values", symbol_count)));
+    }
+
+    let symbols: Vec<u8> = if symbol_count > MAX_SPARSE_SYMBOLS {
+        (0..=255).collect()
+    } else {
+        let symbols = reader.take(symbol_count)?;
+        if symbols.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::corrupt_at(start, "table symbols are not in increasing order"));
+        }
+        symbols.to_vec()
+    };
+    let lengths = read_nibble_lengths(reader, symbols.len())?;
+
+    let table: Vec<(u8, usize)> = symbols.into_iter().zip(lengths).filter(|&(_, len)| len > 0).collect();
+    if table.len() != symbol_count {
+        return Err(Error::corrupt_at(start, "table symbol count doesn't match its code lengths"));
+    }
+    Ok(table)
+}
+
+
+/// Code tables of the contexts: the fallback table, and the own table of the contexts which are better off with one.
+struct ContextTables {
+    fallback: CodeMap<u8>,
+    own: Vec<Option<CodeMap<u8>>>,
+}
+
+
+/// Chooses the contexts which get their own table, and builds the tables.
+///
+/// A context gets its own table when the bits it saves pay for the table in the header. The other contexts, the rare
+/// ones and those whose statistics look like the rest, are gathered into the fallback table, built on their frequencies
+/// only. As the fallback table changes with the contexts it serves, the choice is made again a few times.
+fn plan_tables(frequencies: &[HashMap<u8, u64>], max_code_length: Option<usize>) -> Result<ContextTables, Error> {
+    let mut own: Vec<Option<CodeMap<u8>>> = Vec::with_capacity(CONTEXTS);
+    for context_frequencies in frequencies {
+        own.push((!context_frequencies.is_empty())
+            .then(|| build_canonical_bitvec_map_from_frequencies(context_frequencies, max_code_length))
+            .transpose()?);
+    }
+
+    let mut uses_fallback = vec![true; CONTEXTS];
+    let mut fallback = CodeMap::new();
+    for _ in 0..PLANNING_PASSES {
+        let mut fallback_frequencies: HashMap<u8, u64> = HashMap::new();
+        for (context_frequencies, _) in frequencies.iter().zip(&uses_fallback).filter(|(_, uses)| **uses) {
+            for (&symbol, &count) in context_frequencies {
+                *fallback_frequencies.entry(symbol).or_insert(0) += count;
+            }
+        }
+        fallback = build_canonical_bitvec_map_from_frequencies(&fallback_frequencies, max_code_length)?;
+
+        for (context, context_frequencies) in frequencies.iter().enumerate() {
+            let Some(own_map) = &own[context] else { continue };
+            let own_bits = 8 * (varint_size(own_map.len() as u64) + table_size(own_map.len())) as u64
+                + coded_bits(context_frequencies, own_map).expect("table built on these frequencies");
+            uses_fallback[context] = coded_bits(context_frequencies, &fallback).is_some_and(|bits| bits <= own_bits);
+        }
+    }
+
+    for (context, uses) in uses_fallback.into_iter().enumerate() {
+        if uses {
+            own[context] = None;
+        }
+    }
+    Ok(ContextTables { fallback, own })
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Returns the archive of the files `files`, using order-1 context Huffman coding on their bytes: each byte is coded
+/// with the table of the byte before it, so a byte which nearly always follows another one (`u` after `q`) costs
+/// nearly nothing.
+///
+/// The method header holds the fallback table, then a bitmap of the contexts having their own table (32 bytes, one bit
+/// per context, least significant bit first), then the table of each of these contexts, in order (see `write_table`).
+/// The tables are shared by all files. The payload of each file is the bitstream of its codes (see `BitWriter::finish`).
+pub fn encode_context_huffman(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    let max_code_length = nibble_code_length(options.max_code_length);
+    let ContextTables { fallback, own } = plan_tables(&context_frequencies(files), max_code_length)?;
+
+    let mut method_header = Vec::new();
+    write_table(&mut method_header, &fallback);
+    let mut bitmap = [0u8; CONTEXTS / 8];
+    for (context, table) in own.iter().enumerate() {
+        if table.is_some() {
+            bitmap[context / 8] |= 1 << (context % 8);
+        }
+    }
+    method_header.extend_from_slice(&bitmap);
+    for table in own.iter().flatten() {
+        write_table(&mut method_header, table);
+    }
+
+    let tables: Vec<&CodeMap<u8>> = own.iter().map(|table| table.as_ref().unwrap_or(&fallback)).collect();
+    let mut members = Vec::new();
+    for (name, content) in files {
+        let mut encoded = BitWriter::new();
+        let mut previous = 0;
+        for &byte in content {
+            encoded.write_bits(&tables[previous as usize][&byte]);
+            previous = byte;
+        }
+
+        members.push(Member { name: name.clone(), original_len: content.len() as u64, payload: encoded.finish() });
+    }
+
+    Ok(Archive { method: EncodingMethod::ContextHuffman, method_header, members })
+}
+
+
+/// Decodes archives written by `encode_context_huffman`, returning the content of each of their files.
+pub fn decode_context_huffman(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let mut decoders = vec![CanonicalDecoder::from_lengths(&read_table(&mut reader)?)?];
+
+    // Index in `decoders` of the table of each context, 0 being the fallback table
+    let bitmap = reader.take(CONTEXTS / 8)?;
+    let mut context_decoder = [0; CONTEXTS];
+    for (context, decoder) in context_decoder.iter_mut().enumerate() {
+        if bitmap[context / 8] & (1 << (context % 8)) != 0 {
+            *decoder = decoders.len();
+            decoders.push(CanonicalDecoder::from_lengths(&read_table(&mut reader)?)?);
+        }
+    }
+    if reader.position() != archive.method_header.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the context tables"));
+    }
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let mut bits = BitReader::new(&member.payload)?;
+        let mut content = Vec::new();
+        let mut previous = 0;
+        while (content.len() as u64) < member.original_len {
+            let byte = decoders[context_decoder[previous as usize]].decode_symbol(&mut bits)?;
+            content.push(byte);
+            previous = byte;
+        }
+        if bits.remaining() != 0 {
+            return Err(Error::corrupt(format!("{} bits left after the end of the file", bits.remaining())));
+        }
+
+        contents.push(content);
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_round_trip_in_both_forms() {
+        for symbol_count in [0, 1, 2, MAX_SPARSE_SYMBOLS, MAX_SPARSE_SYMBOLS + 1, 256] {
+            let frequencies: HashMap<u8, u64> = (0..symbol_count).map(|symbol| (symbol as u8, symbol as u64 + 1)).collect();
+            let code_map = build_canonical_bitvec_map_from_frequencies(&frequencies, nibble_code_length(None)).unwrap();
+
+            let mut bytes = Vec::new();
+            write_table(&mut bytes, &code_map);
+            assert_eq!(bytes.len(), varint_size(symbol_count as u64) + table_size(symbol_count));
+
+            let mut table = read_table(&mut ByteReader::new(&bytes)).unwrap();
+            table.sort_unstable();
+            let mut expected = sorted_code_lengths(&code_map);
+            expected.sort_unstable();
+            assert_eq!(table, expected, "{} symbols", symbol_count);
+        }
+    }
+
+    #[test]
+    fn predictable_contexts_get_their_own_table() {
+        // 'q' is always followed by 'u', which is rare elsewhere
+        let text = "the quick queen quietly quotes the quaint quartet, then the others answer ".repeat(50);
+        let tables = plan_tables(&context_frequencies(&[(String::new(), text.into_bytes())]), Some(15)).unwrap();
+
+        let q_table = tables.own[b'q' as usize].as_ref().unwrap();
+        assert_eq!(q_table.len(), 1);
+        assert_eq!(q_table[&b'u'].len(), 1);
+        // '?' never appears, so its context has no table
+        assert!(tables.own[b'?' as usize].is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use crate::{EncodingMethod, Options};
+use crate::archive::{Archive, ByteReader, Member, NamedFile};
+use crate::bitstream::{BitReader, BitWriter};
+use crate::error::Error;
+use crate::huffman::{build_canonical_bitvec_map_from_frequencies, nibble_code_length, read_alphabet_lengths, write_alphabet_lengths, CanonicalDecoder};
+use crate::lz77::{check_match_options, tokenize_with, Token, MIN_MATCH};
+
+
+// ---------- Alphabets -----------
+// Same alphabets as DEFLATE (RFC 1951): symbols 0 to 255 are literals and 257 to 285 match lengths, each length code
+// standing for a range of lengths told apart by the extra bits following it. Distances have their own alphabet of 30
+// codes, built the same way. Symbol 256 (end of block in DEFLATE) isn't used: files end at their original length.
+
+/// Number of symbols of the literal/length alphabet, and of the distance alphabet.
+const LITERAL_LENGTH_SYMBOLS: usize = 286;
+const DISTANCE_SYMBOLS: usize = 30;
+
+/// First literal/length symbol standing for a length.
+const FIRST_LENGTH_SYMBOL: u16 = 257;
+
+/// Smallest length of each length code, and number of extra bits following it.
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [usize; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// Smallest distance of each distance code, and number of extra bits following it.
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [usize; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Longest match and farthest distance the alphabets can express.
+pub const MAX_DEFLATE_MATCH: usize = 258;
+pub const MAX_DEFLATE_DISTANCE: usize = 32768;
+
+/// Matches of `MIN_MATCH` bytes farther than that cost more bits than their literals, and are dropped.
+const MAX_SHORT_MATCH_DISTANCE: usize = 4096;
+
+
+/// Returns the code standing for `value` in a table of base values: the last one not above it.
+fn code_of(value: usize, bases: &[usize]) -> usize {
+    bases.partition_point(|&base| base <= value) - 1
+}
+
+
+/// Symbol of the literal/length alphabet, or of the distance alphabet, with the extra bits following its code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Coded {
+    symbol: u16,
+    extra: u64,
+    extra_bits: usize,
+}
+
+/// Returns the literal/length symbol of a token, and the distance symbol of matches.
+fn code_token(token: Token) -> (Coded, Option<Coded>) {
+    match token {
+        Token::Literal(byte) => (Coded { symbol: byte as u16, extra: 0, extra_bits: 0 }, None),
+        Token::Match { offset, length } => {
+            let length_code = code_of(length, &LENGTH_BASE);
+            let distance_code = code_of(offset, &DISTANCE_BASE);
+            let length = Coded {
+                symbol: FIRST_LENGTH_SYMBOL + length_code as u16,
+                extra: (length - LENGTH_BASE[length_code]) as u64,
+                extra_bits: LENGTH_EXTRA_BITS[length_code],
+            };
+            let distance = Coded {
+                symbol: distance_code as u16,
+                extra: (offset - DISTANCE_BASE[distance_code]) as u64,
+                extra_bits: DISTANCE_EXTRA_BITS[distance_code],
+            };
+            (length, Some(distance))
+        },
+    }
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Returns the archive of the files `files`: each file is cut into literals and matches by LZ77 (see `tokenize_with`),
+/// then the literals and match lengths are Huffman coded with one canonical code, and the match distances with another.
+///
+/// The window size and maximum match length of `options` are capped to what the alphabets can express (32768 and
+/// 258 bytes). The method header holds the code lengths of the literal/length alphabet then of the distance alphabet
+/// (see `write_alphabet_lengths`), shared by all files. The payload of each file is the bitstream of its tokens (see
+/// `BitWriter::finish`): the code of each literal, or the code of the length of each match, its extra bits, the code of
+/// its distance and its extra bits (extra bits are written most significant first).
+pub fn encode_deflate(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    check_match_options(options)?;
+    let window_size = options.window_size.min(MAX_DEFLATE_DISTANCE);
+    let max_match = options.max_match.min(MAX_DEFLATE_MATCH);
+    let max_code_length = nibble_code_length(options.max_code_length);
+
+    let coded_files: Vec<Vec<(Coded, Option<Coded>)>> = files.iter()
+        .map(|(_, content)| {
+            let keep = |offset, length| length > MIN_MATCH || offset <= MAX_SHORT_MATCH_DISTANCE;
+            tokenize_with(content, window_size, max_match, keep).into_iter().map(code_token).collect()
+        })
+        .collect();
+
+    let mut literal_length_frequencies: HashMap<u16, u64> = HashMap::new();
+    let mut distance_frequencies: HashMap<u16, u64> = HashMap::new();
+    for &(literal_length, distance) in coded_files.iter().flatten() {
+        *literal_length_frequencies.entry(literal_length.symbol).or_insert(0) += 1;
+        if let Some(distance) = distance {
+            *distance_frequencies.entry(distance.symbol).or_insert(0) += 1;
+        }
+    }
+    let literal_length_map = build_canonical_bitvec_map_from_frequencies(&literal_length_frequencies, max_code_length)?;
+    let distance_map = build_canonical_bitvec_map_from_frequencies(&distance_frequencies, max_code_length)?;
+
+    let mut method_header = Vec::new();
+    write_alphabet_lengths(&mut method_header, &literal_length_map);
+    write_alphabet_lengths(&mut method_header, &distance_map);
+
+    let mut members = Vec::new();
+    for ((name, content), coded) in files.iter().zip(&coded_files) {
+        let mut encoded = BitWriter::new();
+        for &(literal_length, distance) in coded {
+            for (coded, code_map) in [(Some(literal_length), &literal_length_map), (distance, &distance_map)] {
+                let Some(coded) = coded else { continue };
+                encoded.write_bits(&code_map[&coded.symbol]);
+                encoded.write_int(coded.extra, coded.extra_bits);
+            }
+        }
+
+        members.push(Member { name: name.clone(), original_len: content.len() as u64, payload: encoded.finish() });
+    }
+
+    Ok(Archive { method: EncodingMethod::Deflate, method_header, members })
+}
+
+
+/// Decodes archives written by `encode_deflate`, returning the content of each of their files.
+pub fn decode_deflate(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let literal_length_decoder = CanonicalDecoder::from_lengths(&read_alphabet_lengths(&mut reader, LITERAL_LENGTH_SYMBOLS)?)?;
+    let distance_decoder = CanonicalDecoder::from_lengths(&read_alphabet_lengths(&mut reader, DISTANCE_SYMBOLS)?)?;
+    if reader.position() != archive.method_header.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the code tables"));
+    }
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let mut bits = BitReader::new(&member.payload)?;
+        let mut content: Vec<u8> = Vec::new();
+
+        while (content.len() as u64) < member.original_len {
+            let symbol = literal_length_decoder.decode_symbol(&mut bits)?;
+            if symbol < 256 {
+                content.push(symbol as u8);
+                continue;
+            }
+
+            let length_code = symbol.checked_sub(FIRST_LENGTH_SYMBOL).map(usize::from).filter(|&code| code < LENGTH_BASE.len())
+                .ok_or(Error::corrupt(format!("invalid literal/length symbol {}", symbol)))?;
+            let length = LENGTH_BASE[length_code] + bits.read_int(LENGTH_EXTRA_BITS[length_code])? as usize;
+
+            let distance_code = distance_decoder.decode_symbol(&mut bits)? as usize;
+            let distance = DISTANCE_BASE[distance_code] + bits.read_int(DISTANCE_EXTRA_BITS[distance_code])? as usize;
+
+            if distance > content.len() {
+                return Err(Error::corrupt(format!("match {} bytes back, after {} bytes", distance, content.len())));
+            }
+            if content.len() as u64 + length as u64 > member.original_len {
+                return Err(Error::corrupt("match goes past the end of the file"));
+            }
+            let from = content.len() - distance;
+            for i in 0..length {
+                content.push(content[from + i]);
+            }
+        }
+
+        if bits.remaining() != 0 {
+            return Err(Error::corrupt(format!("{} bits left after the end of the file", bits.remaining())));
+        }
+        contents.push(content);
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_get_deflate_codes() {
+        assert_eq!(code_token(Token::Literal(b'a')), (Coded { symbol: 97, extra: 0, extra_bits: 0 }, None));
+
+        let (length, distance) = code_token(Token::Match { offset: 1000, length: 20 });
+        assert_eq!(length, Coded { symbol: 269, extra: 1, extra_bits: 2 });
+        assert_eq!(distance, Some(Coded { symbol: 19, extra: 231, extra_bits: 8 }));
+
+        let (length, distance) = code_token(Token::Match { offset: 32768, length: 258 });
+        assert_eq!(length, Coded { symbol: 285, extra: 0, extra_bits: 0 });
+        assert_eq!(distance, Some(Coded { symbol: 29, extra: 8191, extra_bits: 13 }));
+    }
+}
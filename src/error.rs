@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io;
+
+
+/// Errors returned by the library.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file (or any reader / writer given to the library) failed
+    Io(io::Error),
+    /// The data being decoded wasn't written by this program, or was damaged. `offset` is the position in bytes of the
+    /// problem in the data being read (the archive, a method header or a payload), when it is known.
+    CorruptArchive { offset: Option<u64>, reason: String },
+    /// The archive was written with another version of the format
+    UnsupportedVersion { found: u8, supported: u8 },
+    /// The arguments or the content given to the library can't be used (bad option, text method on a binary file...)
+    InvalidArgument(String),
+    /// The content doesn't fit in the limits of the format (file name or table too long, too many symbols...)
+    LimitExceeded(String),
+}
+
+impl Error {
+    /// Corrupt archive error whose position isn't known.
+    pub fn corrupt(reason: impl Into<String>) -> Error {
+        Error::CorruptArchive { offset: None, reason: reason.into() }
+    }
+
+    /// Corrupt archive error found at `offset` bytes in the data being read.
+    pub fn corrupt_at(offset: usize, reason: impl Into<String>) -> Error {
+        Error::CorruptArchive { offset: Some(offset as u64), reason: reason.into() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::CorruptArchive { offset: Some(offset), reason } => write!(f, "Corrupt archive: {} (at offset {})", reason, offset),
+            Error::CorruptArchive { offset: None, reason } => write!(f, "Corrupt archive: {}", reason),
+            Error::UnsupportedVersion { found, supported } => {
+                write!(f, "Unsupported archive format version {} (this program reads version {}).", found, supported)
+            },
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+            Error::LimitExceeded(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        // Errors of the library going through the `Read` and `Write` adapters come back as they were
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = err.into_inner().expect("checked above");
+            return *inner.downcast::<Error>().expect("checked above");
+        }
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::CorruptArchive { .. } | Error::UnsupportedVersion { .. } => io::ErrorKind::InvalidData,
+            Error::InvalidArgument(_) | Error::LimitExceeded(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
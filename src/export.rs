@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::{Config, EncodingMethod, Options};
+use crate::archive::read_input_files;
+use crate::error::Error;
+use crate::huffman::{build_canonical_code_from_lengths, build_code_map, code_lengths, create_tree, symbol_parser, HuffmanSymbol, Node};
+
+
+// ---------- Export options -----------
+
+/// Text format the tree is exported to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// Graphviz graph, to render with `dot -Tsvg`
+    Dot,
+    Json,
+}
+
+impl ExportFormat {
+    /// Returns the format matching the name given with the `-e` option.
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "dot" => Some(ExportFormat::Dot),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+
+/// Which tree is exported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeShape {
+    /// The tree built by the Huffman algorithm, before the codes are made canonical. Its codes are the paths to the
+    /// leaves, whatever their length.
+    Huffman,
+    /// The tree of the canonical codes stored in archives, with the code length limit applied.
+    Canonical,
+}
+
+impl TreeShape {
+    /// Returns the tree shape matching the name given with the `--tree` option.
+    pub fn from_name(name: &str) -> Option<TreeShape> {
+        match name {
+            "huffman" => Some(TreeShape::Huffman),
+            "canonical" => Some(TreeShape::Canonical),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TreeShape::Huffman => "huffman",
+            TreeShape::Canonical => "canonical",
+        }
+    }
+}
+
+
+/// Symbols whose tree can be exported: they know how to show themselves in a graph and in JSON.
+pub trait ExportSymbol: HuffmanSymbol {
+    /// Readable name of the symbol, for the nodes of the graph.
+    fn label(self) -> String;
+
+    /// JSON value of the symbol.
+    fn to_json(self) -> String;
+}
+
+impl ExportSymbol for char {
+    fn label(self) -> String {
+        format!("'{}'", self.escape_debug())
+    }
+
+    fn to_json(self) -> String {
+        json_string(&self.to_string())
+    }
+}
+
+impl ExportSymbol for u8 {
+    fn label(self) -> String {
+        format!("0x{:02X}", self)
+    }
+
+    fn to_json(self) -> String {
+        self.to_string()
+    }
+}
+
+
+// #################################
+// ------- Tree --------------------
+// #################################
+
+/// Node of an exported tree. The children of a branch are indices in `ExportTree::nodes`, left being the 0 bit.
+pub enum ExportNode<S> {
+    Branch { weight: u64, left: usize, right: usize },
+    Leaf { symbol: S, frequency: u64, code: String },
+}
+
+impl<S> ExportNode<S> {
+    fn weight(&self) -> u64 {
+        match *self {
+            ExportNode::Branch { weight, .. } => weight,
+            ExportNode::Leaf { frequency, .. } => frequency,
+        }
+    }
+}
+
+
+/// Huffman tree with the frequency and the code of each symbol, ready to be written as DOT or JSON.
+///
+/// A lone symbol is the root of its tree, and still gets the 1-bit code `0`, as in archives.
+pub struct ExportTree<S> {
+    pub shape: TreeShape,
+    pub nodes: Vec<ExportNode<S>>,
+    pub root: Option<usize>,
+}
+
+impl<S: ExportSymbol> ExportTree<S> {
+    /// Builds the tree of the symbols of `frequencies`, `max_code_length` only applying to the canonical tree.
+    pub fn build(frequencies: &HashMap<S, u64>, shape: TreeShape, max_code_length: Option<usize>) -> Result<ExportTree<S>, Error> {
+        let mut freq_vec: Vec<(S, u64)> = frequencies.iter().map(|(&s, &f)| (s, f)).collect();
+        freq_vec.sort_unstable();
+
+        if let [(symbol, frequency)] = freq_vec[..] {
+            return Ok(ExportTree { shape, nodes: vec![ExportNode::Leaf { symbol, frequency, code: "0".into() }], root: Some(0) });
+        }
+
+        Ok(match shape {
+            TreeShape::Huffman => ExportTree::huffman(&freq_vec),
+            TreeShape::Canonical => ExportTree::canonical(&freq_vec, max_code_length)?,
+        })
+    }
+
+    /// Copies the tree of `create_tree`, the code of each leaf being its path from the root.
+    fn huffman(freq_vec: &[(S, u64)]) -> ExportTree<S> {
+        let tree = create_tree(freq_vec);
+        let code_map = build_code_map(&tree);
+
+        let nodes = tree.nodes.iter().map(|node| match *node {
+            Node::Branch(left, weight, right) => ExportNode::Branch { weight, left, right },
+            Node::Leaf(symbol, frequency) => {
+                let code = code_map[&symbol].iter().map(|&bit| if bit { '1' } else { '0' }).collect();
+                ExportNode::Leaf { symbol, frequency, code }
+            },
+        }).collect();
+
+        ExportTree { shape: TreeShape::Huffman, nodes, root: tree.root() }
+    }
+
+    /// Builds the tree of the canonical codes, from the deepest level up.
+    ///
+    /// At each depth, the canonical codes go to the leaves of that length first, in canonical order, then to the
+    /// branches, which are the nodes of the next depth paired two by two in order.
+    fn canonical(freq_vec: &[(S, u64)], max_code_length: Option<usize>) -> Result<ExportTree<S>, Error> {
+        let lengths = code_lengths(freq_vec, max_code_length)?;
+        let code_map = build_canonical_code_from_lengths(&lengths);
+        let frequencies: HashMap<S, u64> = freq_vec.iter().copied().collect();
+
+        let mut leaves = lengths.clone();
+        leaves.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        let max_len = leaves.last().map_or(0, |&(_, len)| len);
+
+        let mut nodes = Vec::new();
+        let mut level: Vec<usize> = Vec::new();
+        for depth in (0..=max_len).rev() {
+            let mut next_level = Vec::new();
+            for &(symbol, _) in leaves.iter().filter(|&&(_, len)| len == depth) {
+                let code = code_map[&symbol].iter().map(|bit| if *bit { '1' } else { '0' }).collect();
+                next_level.push(nodes.len());
+                nodes.push(ExportNode::Leaf { symbol, frequency: frequencies[&symbol], code });
+            }
+            // Huffman and package-merge codes are complete, so every branch has two children
+            for pair in level.chunks_exact(2) {
+                let weight = nodes[pair[0]].weight() + nodes[pair[1]].weight();
+                next_level.push(nodes.len());
+                nodes.push(ExportNode::Branch { weight, left: pair[0], right: pair[1] });
+            }
+            level = next_level;
+        }
+
+        Ok(ExportTree { shape: TreeShape::Canonical, nodes, root: level.first().copied() })
+    }
+
+    /// Returns the leaves as (symbol, frequency, code) in canonical order: by code length, then by symbol.
+    pub fn leaves(&self) -> Vec<(S, u64, &str)> {
+        let mut leaves: Vec<(S, u64, &str)> = self.nodes.iter().filter_map(|node| match node {
+            ExportNode::Leaf { symbol, frequency, code } => Some((*symbol, *frequency, code.as_str())),
+            ExportNode::Branch { .. } => None,
+        }).collect();
+        leaves.sort_by(|a, b| a.2.len().cmp(&b.2.len()).then(a.0.cmp(&b.0)));
+        leaves
+    }
+
+
+    // ---------- Writers ---------
+
+    /// Returns the tree as a Graphviz graph. Branches show their weight, leaves their symbol, frequency and code,
+    /// and each edge the bit it stands for.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph {} {{\n    node [shape=circle];\n", self.shape.name());
+        for (index, node) in self.nodes.iter().enumerate() {
+            match node {
+                ExportNode::Branch { weight, left, right } => {
+                    writeln!(dot, "    n{} [label=\"{}\"];", index, weight).unwrap();
+                    writeln!(dot, "    n{} -> n{} [label=\"0\"];", index, left).unwrap();
+                    writeln!(dot, "    n{} -> n{} [label=\"1\"];", index, right).unwrap();
+                },
+                ExportNode::Leaf { symbol, frequency, code } => {
+                    let label = format!("{}\\n{}\\n{}", dot_escape(&symbol.label()), frequency, code);
+                    writeln!(dot, "    n{} [shape=box, label=\"{}\"];", index, label).unwrap();
+                },
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns the tree as JSON: `tree` (its shape), `symbols` (symbol, frequency, code length and code of each leaf,
+    /// in canonical order) and `root`, the nested nodes (`weight`, `left` and `right` for branches, `symbol`,
+    /// `frequency` and `code` for leaves), `null` for an empty tree.
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\n  \"tree\": \"{}\",\n  \"symbols\": [", self.shape.name());
+        for (index, (symbol, frequency, code)) in self.leaves().into_iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(json, "{}\n    {{\"symbol\": {}, \"frequency\": {}, \"length\": {}, \"code\": \"{}\"}}",
+                separator, symbol.to_json(), frequency, code.len(), code).unwrap();
+        }
+        json.push_str(if self.nodes.is_empty() { "],\n  \"root\": " } else { "\n  ],\n  \"root\": " });
+
+        match self.root {
+            Some(root) => self.write_json_node(&mut json, root, 1),
+            None => json.push_str("null"),
+        }
+        json.push_str("\n}\n");
+        json
+    }
+
+    fn write_json_node(&self, json: &mut String, index: usize, indent: usize) {
+        let pad = "  ".repeat(indent);
+        match &self.nodes[index] {
+            ExportNode::Branch { weight, left, right } => {
+                write!(json, "{{\n{}  \"weight\": {},\n{}  \"left\": ", pad, weight, pad).unwrap();
+                self.write_json_node(json, *left, indent + 1);
+                write!(json, ",\n{}  \"right\": ", pad).unwrap();
+                self.write_json_node(json, *right, indent + 1);
+                write!(json, "\n{}}}", pad).unwrap();
+            },
+            ExportNode::Leaf { symbol, frequency, code } => {
+                write!(json, "{{\"symbol\": {}, \"frequency\": {}, \"code\": \"{}\"}}", symbol.to_json(), frequency, code).unwrap();
+            },
+        }
+    }
+}
+
+
+/// Returns `text` as a JSON string, quotes included.
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => write!(json, "\\u{:04x}", ch as u32).unwrap(),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+
+/// Escapes the quotes and backslashes of a Graphviz label.
+fn dot_escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+// #################################
+// ------- Export ------------------
+// #################################
+
+/// Returns the Huffman tree of `content` in the format `format`, on the symbols of the encoding method of `options`
+/// (characters for `huffman`, bytes for `huffman-bytes`), the canonical tree following its code length limit.
+pub fn export_tree(content: &[u8], options: &Options, shape: TreeShape, format: ExportFormat) -> Result<String, Error> {
+    match options.encoding {
+        EncodingMethod::Huffman => export_symbol_tree::<char>(content, options, shape, format),
+        EncodingMethod::HuffmanBytes => export_symbol_tree::<u8>(content, options, shape, format),
+        method => Err(Error::InvalidArgument(format!("The tree can only be exported for the huffman and huffman-bytes methods, not {:?}.", method))),
+    }
+}
+
+
+fn export_symbol_tree<S: ExportSymbol>(content: &[u8], options: &Options, shape: TreeShape, format: ExportFormat) -> Result<String, Error> {
+    let tree = ExportTree::build(&symbol_parser(&S::split(content)?), shape, options.max_code_length)?;
+    Ok(match format {
+        ExportFormat::Dot => tree.to_dot(),
+        ExportFormat::Json => tree.to_json(),
+    })
+}
+
+
+/// Prints the tree of the files given in the Config struct, all together, as the shared table of an archive would
+/// be built. Archives (`.zip` files) stand for the files they hold.
+pub fn export_files(config: &Config, shape: TreeShape, format: ExportFormat) -> Result<(), Error> {
+    let content: Vec<u8> = read_input_files(&config.files)?.into_iter().flat_map(|(_, content)| content).collect();
+
+    print!("{}", export_tree(&content, &config.options, shape, format)?);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_tree_holds_the_archive_codes() {
+        let frequencies = symbol_parser(&"abracadabra, abracadabra!".chars().collect::<Vec<_>>());
+        let tree = ExportTree::build(&frequencies, TreeShape::Canonical, Some(3)).unwrap();
+
+        let root = tree.root.unwrap();
+        assert_eq!(tree.nodes[root].weight(), 25);
+        for (symbol, frequency, code) in tree.leaves() {
+            assert_eq!(frequency, frequencies[&symbol]);
+            assert!(code.len() <= 3);
+
+            // Following the code from the root reaches the symbol
+            let mut node = root;
+            for bit in code.chars() {
+                let ExportNode::Branch { left, right, .. } = tree.nodes[node] else { panic!("code goes through a leaf") };
+                node = if bit == '0' { left } else { right };
+            }
+            assert!(matches!(tree.nodes[node], ExportNode::Leaf { symbol: leaf, .. } if leaf == symbol));
+        }
+    }
+
+    #[test]
+    fn tree_exports_to_json_and_dot() {
+        let options = Options::with_encoding(EncodingMethod::Huffman);
+        let json = export_tree(b"aab\"", &options, TreeShape::Huffman, ExportFormat::Json).unwrap();
+        assert_eq!(json, r#"{
+  "tree": "huffman",
+  "symbols": [
+    {"symbol": "a", "frequency": 2, "length": 1, "code": "0"},
+    {"symbol": "\"", "frequency": 1, "length": 2, "code": "10"},
+    {"symbol": "b", "frequency": 1, "length": 2, "code": "11"}
+  ],
+  "root": {
+    "weight": 4,
+    "left": {"symbol": "a", "frequency": 2, "code": "0"},
+    "right": {
+      "weight": 2,
+      "left": {"symbol": "\"", "frequency": 1, "code": "10"},
+      "right": {"symbol": "b", "frequency": 1, "code": "11"}
+    }
+  }
+}
+"#);
+        assert_eq!(export_tree(b"", &options, TreeShape::Canonical, ExportFormat::Json).unwrap(),
+            "{\n  \"tree\": \"canonical\",\n  \"symbols\": [],\n  \"root\": null\n}\n");
+
+        let dot = export_tree(b"\x00\x00\x01", &Options::with_encoding(EncodingMethod::HuffmanBytes), TreeShape::Canonical, ExportFormat::Dot).unwrap();
+        assert!(dot.starts_with("digraph canonical {"));
+        assert!(dot.contains("[shape=box, label=\"0x00\\n2\\n0\"];"));
+        assert!(dot.contains("-> n1 [label=\"1\"];"));
+    }
+}
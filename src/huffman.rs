@@ -1,61 +1,96 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::path::Path;
-use std::error::Error;
-use std::fs::{self, read_to_string, OpenOptions};
-use std::io::Write;
+use std::fs::{self, read_to_string};
 
 use bitvec::prelude::* ;
 
-// ---------- FLAGS --------------------
-
-static DEBUG: bool = false;
+use crate::{EncodingMethod, Options};
+use crate::error::Error;
+use crate::presets::Preset;
+use crate::bitstream::{take_bitstream, BitReader, BitWriter};
+use crate::archive::{read_varint_with, varint_size, write_varint, Archive, ByteReader, Member, NamedFile};
 
 
 // ---------- Tree functions -----------
 
+/// Node of a Huffman tree. The children of a branch are indices in `Tree::nodes`.
 #[derive(Debug)]
-pub enum Tree {
-    Branch(Box<Tree>, u16, Box<Tree>),
-    Leaf(char, u16),
+pub enum Node<S> {
+    Branch(usize, u64, usize),
+    Leaf(S, u64),
 }
 
-impl Tree {
-    pub fn extract_val(&self) -> u16 {
+impl<S> Node<S> {
+    pub fn extract_val(&self) -> u64 {
         match *self {
-            Tree::Branch(_,val,_) => val,
-            Tree::Leaf(_, val)    => val,
+            Node::Branch(_,val,_) => val,
+            Node::Leaf(_, val)    => val,
         }
     }
 }
 
 
-/// Takes ownership of two trees and return a parent node of them, which holds ownership of the two children
-pub fn create_node_of_2_mins (left: Tree, right: Tree) -> Tree {
-    let sum_of_freq = left.extract_val() + right.extract_val();
-    Tree::Branch(Box::new(left), sum_of_freq, Box::new(right))
+/// Huffman tree stored in an arena: the nodes live in one vector and point to each other by index.
+/// The leaves come first, in the order of the frequencies the tree was built from, and each branch is pushed after its
+/// two children, so the root is the last node.
+#[derive(Debug)]
+pub struct Tree<S> {
+    pub nodes: Vec<Node<S>>,
 }
 
+impl<S: Copy> Tree<S> {
+    /// Index of the root node, `None` for the tree of an empty alphabet.
+    pub fn root(&self) -> Option<usize> {
+        self.nodes.len().checked_sub(1)
+    }
 
-/// Builds the huffman tree by popping the two least frequent characters from the vector 'freq_vec' and adds them to the main tree by calling create_node_of_2_mins
-/// Then adds the resulting node to the vector. Returns the top root which owns all the tree data
-pub fn create_tree (freq_vec: Vec<(char, u16)>) -> Tree {
-    let mut nodes : Vec<Tree> = freq_vec.into_iter().map(|(c, f)| Tree::Leaf(c, f)).collect(); // Creates a vector of Tree
+    /// Returns the depth of each leaf, which is the length of its code.
+    /// Parents always come after their children, so one pass from the root down gives every depth.
+    pub fn code_lengths(&self) -> Vec<(S, usize)> {
+        let mut depths = vec![0; self.nodes.len()];
+        let mut lengths = Vec::new();
+
+        for (index, node) in self.nodes.iter().enumerate().rev() {
+            match *node {
+                Node::Branch(left, _, right) => {
+                    depths[left] = depths[index] + 1;
+                    depths[right] = depths[index] + 1;
+                },
+                Node::Leaf(symbol, _) => lengths.push((symbol, depths[index])),
+            }
+        }
 
-    while nodes.len() > 1 {
-        nodes.sort_by_key(|tree| std::cmp::Reverse(tree.extract_val()));
-        let left = nodes.pop().unwrap(); 
-        let right = nodes.pop().unwrap();    // can't crash because len >= 2
+        lengths
+    }
+}
 
-        
-        let newnode = create_node_of_2_mins(left, right);
-        nodes.push(newnode);
+
+/// Builds the huffman tree from the frequency of each symbol: the two least frequent nodes are popped from a min-heap
+/// and merged into a branch, which goes back in the heap, until only the root is left. Ties are broken by node index,
+/// so the same frequencies always give the same tree.
+pub fn create_tree<S: Copy> (freq_vec: &[(S, u64)]) -> Tree<S> {
+    let mut nodes: Vec<Node<S>> = freq_vec.iter().map(|&(c, f)| Node::Leaf(c, f)).collect();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = nodes.iter().enumerate().map(|(i, node)| Reverse((node.extract_val(), i))).collect();
+
+    while heap.len() > 1 {
+        let Reverse((left_freq, left)) = heap.pop().unwrap();
+        let Reverse((right_freq, right)) = heap.pop().unwrap();    // can't crash because len >= 2
+
+        let sum_of_freq = left_freq + right_freq;
+        heap.push(Reverse((sum_of_freq, nodes.len())));
+        nodes.push(Node::Branch(left, sum_of_freq, right));
     }
-    nodes.pop().expect("Error in building the tree")
+
+    Tree { nodes }
 }
 
 /// Reads a string and returns a `Hashmap` with every character it contains as key and its number of occurence as value. 
-pub fn parser(content: &String) -> Result<HashMap<char, u16>, std::io::Error> {
+pub fn parser(content: &str) -> Result<HashMap<char, u64>, std::io::Error> {
     let mut frequency_map = HashMap::new();
    
     for c in content.chars() {
@@ -67,33 +102,45 @@ pub fn parser(content: &String) -> Result<HashMap<char, u16>, std::io::Error> {
     Ok(frequency_map)
 }
 
+/// Reads a byte slice and returns a `Hashmap` with every byte value it contains as key and its number of occurence as value. 
+pub fn byte_parser(content: &[u8]) -> HashMap<u8, u64> {
+    let mut frequency_map = HashMap::new();
 
+    for &b in content {
+        *frequency_map.entry(b).or_insert(0) += 1;
+    }
 
-/// Takes the root node of a tree and builds a hashmap giving the binary path in the tree to reach each character
-pub fn build_code_map(tree: &Tree) -> HashMap<char, Vec<bool>> {
-    let mut code_map = HashMap::new();
-    build_code_map_rec(tree, Vec::new(), &mut code_map);
-    code_map
+    frequency_map
 }
 
 
-/// Logical function for 'build_code_map' that recursively runs the tree, writing the path to each character in a map
+
+/// Takes a tree and builds a hashmap giving the binary path in the tree to reach each symbol
 /// (going left = 0 = false, right = 1 = true).
-fn build_code_map_rec(tree: &Tree, path: Vec<bool>, map: &mut HashMap<char, Vec<bool>>) {
-    match tree {
-        Tree::Leaf(ch, _) => {
-            map.insert(*ch, path);
+pub fn build_code_map<S: Copy + Eq + Hash>(tree: &Tree<S>) -> HashMap<S, Vec<bool>> {
+    let mut code_map = HashMap::new();
+    // Depth-first walk sharing a single path: each node comes with its depth and the bit leading to it from its parent,
+    // the path being cut back to the parent before the bit is pushed. Paths are only copied for the leaves
+    let mut path = Vec::new();
+    let mut stack: Vec<(usize, usize, bool)> = tree.root().map(|root| (root, 0, false)).into_iter().collect();
+
+    while let Some((index, depth, bit)) = stack.pop() {
+        if depth > 0 {
+            path.truncate(depth - 1);
+            path.push(bit);
         }
-        Tree::Branch(left, _, right) => {
-            let mut left_path = path.clone();
-            left_path.push(false); // left = 0
-            build_code_map_rec(left, left_path, map);
-
-            let mut right_path = path;
-            right_path.push(true); // right = 1
-            build_code_map_rec(right, right_path, map);
+        match tree.nodes[index] {
+            Node::Leaf(ch, _) => {
+                code_map.insert(ch, path.clone());
+            }
+            Node::Branch(left, _, right) => {
+                stack.push((left, depth + 1, false)); // left = 0
+                stack.push((right, depth + 1, true)); // right = 1
+            }
         }
     }
+
+    code_map
 }
 
 
@@ -163,23 +210,22 @@ pub struct EncodedFile {
 
 impl EncodedFile {
     /// Constructor for the `EncodedFile` struct. This is the function encoding the files' name and content.
-    pub fn build(file: &String, map: &HashMap<char, String>) -> Result<EncodedFile, Box<dyn Error>> {
+    pub fn build(file: &String, map: &HashMap<char, String>) -> Result<EncodedFile, Error> {
        // Keeps only the name of the file without the leading path
-        let name: &str;
-        match file.rsplit_once('/')  {
-            None => name = file,
-            Some(n) => name = n.1,
-        }
+        let name: &str = match file.rsplit_once('/')  {
+            None => file,
+            Some(n) => n.1,
+        };
 
         let mut encoded_name = String::new();
         for char in name.chars() {
-            encoded_name.push_str(map.get(&char).ok_or("Encode_file: the encoded character isn't in the map.")?);
+            encoded_name.push_str(map.get(&char).ok_or(Error::InvalidArgument("Encode_file: the encoded character isn't in the map.".into()))?);
         }
 
         let mut encoded_content = String::new();
         let content = read_to_string(Path::new(&file))?;
         for char in content.chars() {
-            encoded_content.push_str(map.get(&char).ok_or("Encode_file: the encoded character isn't in the map.")?);
+            encoded_content.push_str(map.get(&char).ok_or(Error::InvalidArgument("Encode_file: the encoded character isn't in the map.".into()))?);
         }
 
         
@@ -188,8 +234,8 @@ impl EncodedFile {
 }
 
 /// Builds and returns the canonical map of all the compressed files by calling auxiliary functions
-pub fn build_canonical_map_from_string(content: &String) -> Result<HashMap<char,String>, Box<dyn Error>> {
-    let freq_vec: Vec<(char, u16)> = parser(&content)?
+pub fn build_canonical_map_from_string(content: &str) -> Result<HashMap<char,String>, Error> {
+    let freq_vec: Vec<(char, u64)> = parser(content)?
             .iter()
             .map(|(&c, &f)| (c, f))
             .collect();
@@ -197,12 +243,12 @@ pub fn build_canonical_map_from_string(content: &String) -> Result<HashMap<char,
     
 
     if freq_vec.is_empty() {
-        return Err("encode_file: Cannot compress enmpy files.".into());
+        return Err(Error::InvalidArgument("encode_file: Cannot compress enmpy files.".into()));
     }
 
     // Creates the huffman tree, then the code map and then the canonical code map
-    let tree = create_tree(freq_vec);
-    //print_tree(&tree, 0);
+    let tree = create_tree(&freq_vec);
+    //print_tree(&tree, tree.root().unwrap(), 0);
 
     let code_map = build_code_map(&tree);
     //print_code_map(&code_map);
@@ -217,7 +263,7 @@ pub fn build_canonical_map_from_string(content: &String) -> Result<HashMap<char,
 
 /// Returns a `String` with all the files names and content concatenated. It is useful to have a string containing all the characters
 /// needed to build the canonical map.
-pub fn merge_string(file_paths: &Vec<String>) -> Result<String, Box<dyn Error>> {
+pub fn merge_string(file_paths: &Vec<String>) -> Result<String, Error> {
     let mut content = String::new();
     for file_path in file_paths {      
         // Push name first to make sure the characters in the file name also have a compression symbol
@@ -230,7 +276,7 @@ pub fn merge_string(file_paths: &Vec<String>) -> Result<String, Box<dyn Error>>
 
 
 /// Returns a `String` containing the size of the map, the number of files in the archive and the aforementionned map linking the compressing symbol to each character.
-pub fn add_huffman_header(map: &HashMap<char, String>, files_count: usize) -> Result<String, Box<dyn Error>> {
+pub fn add_huffman_header(map: &HashMap<char, String>, files_count: usize) -> Result<String, Error> {
     let mut header = String::new();
 
     // In order: size of map, number of files
@@ -257,7 +303,7 @@ pub fn add_huffman_header(map: &HashMap<char, String>, files_count: usize) -> Re
 /// - adds the header to the archive, containing the map size, the file count and the map
 /// - encodes each char of the file, with this format : 'file_name.ext&file_content', each file separated by a new line
 /// - writes the archive with the given name contained in the Config struct.
-pub fn encode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error>> {
+pub fn encode_string_huffman(config: &super::Config) -> Result<(), Error> {
     
     let file_paths = &config.files;
 
@@ -291,9 +337,10 @@ pub fn encode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error
 /// ## Exemple :
 /// 
 /// ```rust
-/// assert_eq!("test", extract_archive_name("foo/bar/test.zip"));
+/// use my_compressor::huffman::extract_file_from_path;
+/// assert_eq!("test", extract_file_from_path("foo/bar/test.zip").unwrap());
 /// ```
-pub fn extract_file_from_path (file: &String) -> Result<String, Box<dyn Error>> {
+pub fn extract_file_from_path (file: &str) -> Result<String, Error> {
     let name_without_path=
     match file.rsplit_once('/') {
         None => file,
@@ -311,7 +358,7 @@ pub fn extract_file_from_path (file: &String) -> Result<String, Box<dyn Error>>
 
 /// Reads the <char, symbol> map at the beginning of the huffman-encoded file and returns it, as well as a boolean indicating
 /// if the map contains the character '\n' (useful because causing an empty line in the file).
-pub fn read_canonical_map(encoded_text: &String, map_size: usize) -> Result<(HashMap<String,char>, bool), Box<dyn Error>> {
+pub fn read_canonical_map(encoded_text: &str, map_size: usize) -> Result<(HashMap<String,char>, bool), Error> {
     let mut canonical_map = HashMap::new();
     let mut lines = encoded_text.lines();
     let mut contains_backspace = false;
@@ -319,7 +366,7 @@ pub fn read_canonical_map(encoded_text: &String, map_size: usize) -> Result<(Has
     lines.next();   // Skips the two first lines of the file, which are the map size and the number of files
 
     for _i in 0..map_size {
-        let line = lines.next().ok_or("Missing line in character map")?;
+        let line = lines.next().ok_or(Error::corrupt("Missing line in character map"))?;
 
         // Two characters can cause problems in the format: ':', which is the separator for the map in my zip format, 
         // and '\n', which creates an empty line, so we treat those cases separately
@@ -329,7 +376,7 @@ pub fn read_canonical_map(encoded_text: &String, map_size: usize) -> Result<(Has
             canonical_map.insert(tmp, ':');
             continue;
         } else if line.is_empty(){
-            let tmpline = lines.next().ok_or("Missing line in character map")?;
+            let tmpline = lines.next().ok_or(Error::corrupt("Missing line in character map"))?;
             let mut tmp: String = tmpline.into();
             tmp.drain(..1);
             canonical_map.insert(tmp, '\n');
@@ -339,10 +386,10 @@ pub fn read_canonical_map(encoded_text: &String, map_size: usize) -> Result<(Has
 
         let mut parts = line.splitn(2, ':');
 
-        let ch_str = parts.next().ok_or("Missing character in map entry")?;
-        let code = parts.next().ok_or("Missing code in map entry")?;
+        let ch_str = parts.next().ok_or(Error::corrupt("Missing character in map entry"))?;
+        let code = parts.next().ok_or(Error::corrupt("Missing code in map entry"))?;
 
-        let ch = ch_str.chars().next().ok_or("Empty character in map")?;
+        let ch = ch_str.chars().next().ok_or(Error::corrupt("Empty character in map"))?;
 
         canonical_map.insert(code.to_string(), ch);        
     }
@@ -363,7 +410,7 @@ pub fn read_canonical_map(encoded_text: &String, map_size: usize) -> Result<(Has
 /// - extracts its character map
 /// - decompresses the file name and file content, separated by a '&'
 /// - writes each decompressed file in a subfolder with the same name as the archive
-pub fn decode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error>> {
+pub fn decode_string_huffman(config: &super::Config) -> Result<(), Error> {
     let file_paths = &config.files;
 
     // Decompress each archive one by one
@@ -376,15 +423,17 @@ pub fn decode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error
         // Read and parse map size and fule count
         let char_map_size: usize = lines
             .next()
-            .ok_or("Missing character map size line")?
+            .ok_or(Error::corrupt("Missing character map size line"))?
             .trim()
-            .parse()?;
+            .parse()
+            .map_err(|_| Error::corrupt("Invalid character map size"))?;
 
         let _file_count: usize = lines
             .next()
-            .ok_or("Missing files count line")?
+            .ok_or(Error::corrupt("Missing files count line"))?
             .trim()
-            .parse()?;
+            .parse()
+            .map_err(|_| Error::corrupt("Invalid files count"))?;
 
         
         let (canonical_map, contains_backspace) = read_canonical_map(&encoded_text, char_map_size)?;
@@ -400,7 +449,7 @@ pub fn decode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error
         }
 
         
-        let archive_name = extract_file_from_path(&file)?;
+        let archive_name = extract_file_from_path(file)?;
         if !Path::new(&archive_name).exists() {
             fs::create_dir(&archive_name)?;
         }
@@ -448,16 +497,114 @@ pub fn decode_string_huffman(config: &super::Config) -> Result<(), Box<dyn Error
 
 // ---------- Encoder ---------
 
-pub fn build_canonical_code_bitvec(code_map: HashMap<char, Vec<bool>>) -> HashMap<char, BitVec<u8, Msb0>>  {
-    let mut canonical_vec: Vec<(char, usize)> = code_map.iter().map(|(c, vec)| (*c, vec.len())).collect();
+/// Default maximum length of the Huffman codes, in bits.
+pub const DEFAULT_MAX_CODE_LENGTH: usize = 15;
+
+/// Codes are built in a `u64`, so no code can be longer than that, even when no limit is asked.
+pub const MAX_SUPPORTED_CODE_LENGTH: usize = 64;
+
+
+/// Item of a package-merge list: either a symbol (given by its index) or a package of two items of the previous list.
+enum PackageItem {
+    Leaf(usize),
+    Package(usize, usize),
+}
+
+
+/// Returns the optimal code lengths of the symbols of `freq_vec` such that no code is longer than `max_len` bits,
+/// using the package-merge algorithm.
+/// 
+/// Each symbol starts as a coin whose value is its frequency. At each of the `max_len - 1` steps, the coins of the
+/// current list are paired two by two into packages, which are merged with the original coins. The `2n - 2` cheapest
+/// items of the last list give the code lengths: a symbol's length is the number of times it appears in them.
+pub fn package_merge_lengths<S: Copy>(freq_vec: &[(S, u64)], max_len: usize) -> Result<Vec<(S, usize)>, Error> {
+    let n = freq_vec.len();
+    if n <= 1 {
+        return Ok(freq_vec.iter().map(|&(symbol, _)| (symbol, 0)).collect());
+    }
+    if max_len < usize::BITS as usize && n > 1 << max_len {
+        return Err(Error::LimitExceeded(format!("Cannot give {} symbols codes of at most {} bits.", n, max_len)));
+    }
+
+    let mut leaves: Vec<(u64, usize)> = freq_vec.iter().enumerate().map(|(i, &(_, f))| (f, i)).collect();
+    leaves.sort();
+
+    // lists[0] only holds the leaves, each following list holds leaves and packages of the previous one
+    let mut lists: Vec<Vec<(u64, PackageItem)>> = vec![leaves.iter().map(|&(w, i)| (w, PackageItem::Leaf(i))).collect()];
+    for _ in 1..max_len {
+        let previous = lists.last().unwrap();
+        let packages: Vec<(u64, PackageItem)> = (0..previous.len() / 2)
+            .map(|k| (previous[2 * k].0 + previous[2 * k + 1].0, PackageItem::Package(2 * k, 2 * k + 1)))
+            .collect();
+
+        // Merges the sorted leaves and the sorted packages, leaves first on ties
+        let mut merged = Vec::with_capacity(n + packages.len());
+        let mut packages = packages.into_iter().peekable();
+        for &(w, i) in &leaves {
+            while let Some(package) = packages.next_if(|(pw, _)| *pw < w) {
+                merged.push(package);
+            }
+            merged.push((w, PackageItem::Leaf(i)));
+        }
+        merged.extend(packages);
+        lists.push(merged);
+    }
+
+    // Counts how many times each symbol appears in the 2n - 2 cheapest items of the last list
+    let mut lengths = vec![0; n];
+    let mut stack: Vec<(usize, usize)> = (0..2 * n - 2).map(|k| (lists.len() - 1, k)).collect();
+    while let Some((level, index)) = stack.pop() {
+        match lists[level][index].1 {
+            PackageItem::Leaf(i) => lengths[i] += 1,
+            PackageItem::Package(a, b) => {
+                stack.push((level - 1, a));
+                stack.push((level - 1, b));
+            }
+        }
+    }
+
+    Ok(freq_vec.iter().zip(lengths).map(|(&(symbol, _), len)| (symbol, len)).collect())
+}
+
+
+/// Returns the code length of each symbol of `freq_vec`. A lone symbol gets a code of 1 bit.
+/// Ties between equal frequencies are broken by the order of `freq_vec`, so callers sort it first for the lengths (and
+/// the archive) not to depend on the iteration order of a `HashMap`.
+/// The lengths come from the Huffman tree, unless a code is longer than `max_code_length` (or than 64 bits when there is
+/// no limit), in which case the optimal limited lengths are computed with `package_merge_lengths`.
+pub fn code_lengths<S: Copy + Eq + Hash>(freq_vec: &[(S, u64)], max_code_length: Option<usize>) -> Result<Vec<(S, usize)>, Error> {
+    let max_len = max_code_length.unwrap_or(MAX_SUPPORTED_CODE_LENGTH).min(MAX_SUPPORTED_CODE_LENGTH);
+
+    // A tree with a single leaf gives it an empty code, which couldn't be counted in the bitstream: it gets one bit instead
+    if freq_vec.len() <= 1 {
+        return Ok(freq_vec.iter().map(|&(symbol, _)| (symbol, 1)).collect());
+    }
+
+    let tree = create_tree(freq_vec);
+    //print_tree(&tree, tree.root().unwrap(), 0);
+    let lengths = tree.code_lengths();
+
+    if lengths.iter().all(|&(_, len)| len <= max_len) {
+        return Ok(lengths);
+    }
+
+    package_merge_lengths(freq_vec, max_len)
+}
+
+
+/// Builds the canonical codes from the code length of each symbol: symbols are sorted by code length, then by symbol,
+/// and each code is the previous one plus one, shifted left when the length increases.
+pub fn build_canonical_code_from_lengths<S: Copy + Ord + Hash>(lengths: &[(S, usize)]) -> HashMap<S, BitVec<u8, Msb0>>  {
+    let mut canonical_vec: Vec<(S, usize)> = lengths.to_vec();
 
     canonical_vec.sort_by(|a,b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
 
-    let mut canonical_map: HashMap<char, BitVec<u8, Msb0>> = HashMap::new();
-    let mut code: u32 = 0;
+    let mut canonical_map: HashMap<S, BitVec<u8, Msb0>> = HashMap::new();
+    let mut code: u64 = 0;
     let mut prev_len = 0;
 
     for &(ch, bit_len) in &canonical_vec {
+        assert!(bit_len <= MAX_SUPPORTED_CODE_LENGTH, "Huffman codes can't be longer than 64 bits");
         if bit_len > prev_len {
             code <<= bit_len - prev_len;
         } else if bit_len < prev_len {
@@ -470,7 +617,7 @@ pub fn build_canonical_code_bitvec(code_map: HashMap<char, Vec<bool>>) -> HashMa
         }
 
         canonical_map.insert(ch, bv);
-        code += 1;
+        code = code.wrapping_add(1);
         prev_len = bit_len;
     }
 
@@ -478,26 +625,25 @@ pub fn build_canonical_code_bitvec(code_map: HashMap<char, Vec<bool>>) -> HashMa
 }
 
 
-pub fn build_canonical_bitvec_map_from_string(content: &String) -> Result<HashMap<char, BitVec<u8, Msb0>>, Box<dyn Error>> {
-    let freq_vec: Vec<(char, u16)> = parser(&content)?
+/// Builds the canonical codes from a code map giving the path to each symbol in the Huffman tree.
+pub fn build_canonical_code_bitvec<S: Copy + Ord + Hash>(code_map: HashMap<S, Vec<bool>>) -> HashMap<S, BitVec<u8, Msb0>>  {
+    let lengths: Vec<(S, usize)> = code_map.iter().map(|(c, vec)| (*c, vec.len())).collect();
+    build_canonical_code_from_lengths(&lengths)
+}
+
+
+/// Builds the canonical code map of `content`, no code being longer than `max_code_length` bits.
+pub fn build_canonical_bitvec_map_from_string(content: &str, max_code_length: Option<usize>) -> Result<HashMap<char, BitVec<u8, Msb0>>, Error> {
+    let mut freq_vec: Vec<(char, u64)> = parser(content)?
             .iter()
             .map(|(&c, &f)| (c, f))
             .collect();
+    freq_vec.sort_unstable();
 
-    
+    // Computes the (possibly limited) code lengths, then the canonical code map. Empty content gives an empty map
+    let lengths = code_lengths(&freq_vec, max_code_length)?;
 
-    if freq_vec.is_empty() {
-        return Err("encode_file: Cannot compress enmpy files.".into());
-    }
-
-    // Creates the huffman tree, then the code map and then the canonical code map
-    let tree = create_tree(freq_vec);
-    //print_tree(&tree, 0);
-
-    let code_map = build_code_map(&tree);
-    //print_code_map(&code_map);
-
-    let canonical_code_map: HashMap<char, BitVec<u8, Msb0>>= build_canonical_code_bitvec(code_map);
+    let canonical_code_map: HashMap<char, BitVec<u8, Msb0>>= build_canonical_code_from_lengths(&lengths);
     //println!("{:?}", canonical_code_map);
 
     Ok(canonical_code_map)
@@ -509,7 +655,7 @@ pub fn build_canonical_bitvec_map_from_string(content: &String) -> Result<HashMa
 /// `map size` (u8)
 /// 
 /// `map` with format `char:code`
-pub fn add_huffman_bitvec_header(map: &HashMap<char, BitVec>, files_count: usize) -> Result<String, Box<dyn Error>> {
+pub fn add_huffman_bitvec_header(map: &HashMap<char, BitVec>, files_count: usize) -> Result<String, Error> {
     let mut header = String::new();
 
     // In order: size of map, number of files
@@ -524,223 +670,1025 @@ pub fn add_huffman_bitvec_header(map: &HashMap<char, BitVec>, files_count: usize
 
 
 
-/// Compresses every file provided in the Config struct into a .zip, using the Huffman coding algorithm.
-/// 
-/// This function:
-/// - merges all files (including their name) to have a complete, unique character-to-symbol map
-/// - adds the header to the archive, containing the map size and the map
-/// - encodes each char of the file, with this format : 'file_name.ext\0file_content', each file separated by a null char
-/// - writes the archive with the given name contained in the Config struct.
-pub fn encode_bitvec_huffman(config: &super::Config) -> Result<(), Box<dyn Error>> {
-    let file_paths = &config.files;
-    fs::File::create(Path::new(&config.archive_name))?; // Used to create an empty archive
-    let mut write_file = OpenOptions::new().append(true).create(true).open(Path::new(&config.archive_name))?;
+// ---------- Code length headers ---------
+// Codes are canonical, so the headers only hold the length of each code: the decoder rebuilds the codes from them.
 
-    let mut merged_content = merge_string(file_paths)?;
-    let separator = '\u{0000}';
-    merged_content.push(separator);
-    let code_map: HashMap<char, BitVec<u8, Msb0>> = build_canonical_bitvec_map_from_string(&merged_content)?;
-    
 
-    // This part writes the header of the file containing the map and other useful variables
+/// Returns the (symbol, code length) pairs of a code map, sorted in canonical order: by code length, then by symbol.
+pub fn sorted_code_lengths<S: Copy + Ord>(code_map: &HashMap<S, BitVec<u8, Msb0>>) -> Vec<(S, usize)> {
+    let mut lengths: Vec<(S, usize)> = code_map.iter().map(|(&symbol, code)| (symbol, code.len())).collect();
+    lengths.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+    lengths
+}
+
 
-    // Writes size of the map
-    write_file.write_all(&[code_map.len() as u8])?;
+/// Returns the code lengths of a character map, with format `map size` (varint), then `char` (UTF-8), `code length` (u8)
+/// for each character in canonical order.
+pub fn write_char_lengths(code_map: &HashMap<char, BitVec<u8, Msb0>>) -> Vec<u8> {
+    let mut header: Vec<u8> = Vec::new();
+    write_varint(&mut header, code_map.len() as u64);
 
-    // Loop writing the map at the beginning of the file
-    for (char, code) in &code_map {
+    for (char, len) in sorted_code_lengths(code_map) {
         let mut buf = [0; 4];
-        let char_bytes = char.encode_utf8(&mut buf).as_bytes();
-        
+        header.extend_from_slice(char.encode_utf8(&mut buf).as_bytes());
+        header.push(len as u8);
+    }
 
-        // Char 
-        write_file.write_all(char_bytes)?;
+    header
+}
 
-        // Size of the code_byte
-        write_file.write_all(&[code.len() as u8])?;
 
-        // Code_byte
-        let code_bytes = code.clone().into_vec(); 
-        write_file.write_all(&code_bytes)?;
-        
-        
-        if DEBUG {
-            println!("[DEBUG]{:?}(size {:?}):{:?}", char_bytes, &[code.len() as u8], code.clone().into_vec());
+/// Reads the (character, code length) pairs written by `write_char_lengths`.
+pub fn read_char_lengths(reader: &mut ByteReader) -> Result<Vec<(char, usize)>, Error> {
+    // Number of entries in the code map
+    let map_size = reader.read_varint()? as usize;
+    let mut lengths = Vec::new();
+
+    for _ in 0..map_size {
+        let ch = read_char(reader)?;
+        lengths.push((ch, reader.read_u8()? as usize));
+    }
+
+    Ok(lengths)
+}
+
+
+/// Reads a character written in UTF-8 (1 to 4 bytes).
+pub fn read_char(reader: &mut ByteReader) -> Result<char, Error> {
+    let start = reader.position();
+    let first_byte = reader.read_u8()?;
+    let char_len = match first_byte {
+        0x00..=0x7F => 1,             // ASCII
+        0xC0..=0xDF => 2,             // 2-byte UTF-8
+        0xE0..=0xEF => 3,             // 3-byte UTF-8
+        0xF0..=0xF7 => 4,             // 4-byte UTF-8
+        _ => return Err(Error::corrupt_at(start, "Invalid UTF-8 character prefix")),
+    };
+
+    reader.take(char_len - 1)?;
+    let ch = str::from_utf8(&reader.bytes()[start..reader.position()])
+        .map_err(|_| Error::corrupt_at(start, "Invalid UTF-8 character"))?
+        .chars().next().unwrap();
+    Ok(ch)
+}
+
+
+/// Returns the code length of each of the 256 byte values, in order, 0 standing for the bytes absent from the map.
+pub fn write_byte_lengths(code_map: &HashMap<u8, BitVec<u8, Msb0>>) -> Vec<u8> {
+    let mut header = vec![0; 256];
+    for (&byte, code) in code_map {
+        header[byte as usize] = code.len() as u8;
+    }
+    header
+}
+
+
+/// Reads the (byte, code length) pairs written by `write_byte_lengths`.
+pub fn read_byte_lengths(reader: &mut ByteReader) -> Result<Vec<(u8, usize)>, Error> {
+    Ok(reader.take(256)?
+        .iter()
+        .enumerate()
+        .filter(|&(_, &len)| len > 0)
+        .map(|(byte, &len)| (byte as u8, len as usize))
+        .collect())
+}
+
+
+// ---------- Nibble lengths -----------
+// The methods with several tables (`context`, `deflate`, `bwt`) store each code length in 4 bits, as DEFLATE does.
+// Their codes are therefore never longer than `MAX_NIBBLE_CODE_LENGTH` bits, whatever the options ask.
+
+/// Longest code a length written by `write_nibble_lengths` can stand for.
+pub const MAX_NIBBLE_CODE_LENGTH: usize = 15;
+
+
+/// Returns the code length limit `max_code_length` of the options, capped to `MAX_NIBBLE_CODE_LENGTH`.
+pub fn nibble_code_length(max_code_length: Option<usize>) -> Option<usize> {
+    Some(max_code_length.map_or(MAX_NIBBLE_CODE_LENGTH, |len| len.min(MAX_NIBBLE_CODE_LENGTH)))
+}
+
+
+/// Appends `lengths` to `bytes`, two per byte (high nibble first), an odd count being padded with a 0 nibble.
+/// Every length must be at most `MAX_NIBBLE_CODE_LENGTH`.
+pub fn write_nibble_lengths(bytes: &mut Vec<u8>, lengths: &[usize]) {
+    debug_assert!(lengths.iter().all(|&len| len <= MAX_NIBBLE_CODE_LENGTH));
+    bytes.extend(lengths.chunks(2).map(|pair| ((pair[0] << 4) | pair.get(1).copied().unwrap_or(0)) as u8));
+}
+
+
+/// Reads `count` code lengths written by `write_nibble_lengths`.
+pub fn read_nibble_lengths(reader: &mut ByteReader, count: usize) -> Result<Vec<usize>, Error> {
+    let start = reader.position();
+    let mut lengths: Vec<usize> = reader.take(count.div_ceil(2))?
+        .iter()
+        .flat_map(|&byte| [(byte >> 4) as usize, (byte & 0x0F) as usize])
+        .collect();
+    if count % 2 == 1 && lengths.pop() != Some(0) {
+        return Err(Error::corrupt_at(start + count / 2, "code lengths padded with a non-zero nibble"));
+    }
+    Ok(lengths)
+}
+
+
+/// Appends the code lengths of an alphabet numbered from 0: `symbol count` (varint, up to the last symbol having a
+/// code), then the code length of each symbol (see `write_nibble_lengths`), 0 for the symbols without a code.
+pub fn write_alphabet_lengths(bytes: &mut Vec<u8>, code_map: &CodeMap<u16>) {
+    let symbol_count = code_map.keys().max().map_or(0, |&last| last as usize + 1);
+    let lengths: Vec<usize> = (0..symbol_count as u16).map(|symbol| code_map.get(&symbol).map_or(0, |code| code.len())).collect();
+    write_varint(bytes, symbol_count as u64);
+    write_nibble_lengths(bytes, &lengths);
+}
+
+
+/// Reads the (symbol, code length) pairs written by `write_alphabet_lengths`, for an alphabet of `alphabet_size` symbols.
+pub fn read_alphabet_lengths(reader: &mut ByteReader, alphabet_size: usize) -> Result<Vec<(u16, usize)>, Error> {
+    let start = reader.position();
+    let symbol_count = reader.read_varint()? as usize;
+    if symbol_count > alphabet_size {
+        return Err(Error::corrupt_at(start, format!("{} code lengths for an alphabet of {} symbols", symbol_count, alphabet_size)));
+    }
+
+    Ok(read_nibble_lengths(reader, symbol_count)?
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, len)| len > 0)
+        .map(|(symbol, len)| (symbol as u16, len))
+        .collect())
+}
+
+
+/// Encodes a sequence of symbols with the given code map and returns the bitstream as bytes, prefixed by its exact
+/// length in bits (see `BitWriter::finish`).
+pub fn encode_symbols<S: Copy + Eq + Hash + Debug>(symbols: impl Iterator<Item = S>, code_map: &HashMap<S, BitVec<u8, Msb0>>) -> Result<Vec<u8>, Error> {
+    let mut encoded = BitWriter::new();
+    for symbol in symbols {
+        let code = code_map.get(&symbol).ok_or(Error::InvalidArgument(format!("Missing symbol in code map: {:?}", symbol)))?;
+        encoded.write_bits(code);
+    }
+
+    Ok(encoded.finish())
+}
+
+
+// ---------- Symbols and tables ---------
+
+
+/// Symbols a Huffman archive can be built on: the characters of UTF-8 text files, or the 256 byte values.
+pub trait HuffmanSymbol: Copy + Ord + Hash + Debug {
+    /// Splits the content of a file into symbols.
+    fn split(content: &[u8]) -> Result<Vec<Self>, Error>;
+
+    /// Returns the number of bytes at the start of `content` which form whole symbols. The bytes left are the start of
+    /// a symbol cut by the end of `content`.
+    fn complete_len(content: &[u8]) -> Result<usize, Error>;
+
+    /// Turns decoded symbols back into the bytes of the file.
+    fn join(symbols: Vec<Self>) -> Vec<u8>;
+
+    /// Number of bytes of the original file the symbol stands for.
+    fn byte_len(self) -> u64;
+
+    /// Symbol of the byte value `byte`, for the tables given on bytes (see `Preset`).
+    fn from_byte(byte: u8) -> Self;
+
+    /// Returns the code lengths of a code map, as stored in the archive.
+    fn write_lengths(code_map: &HashMap<Self, BitVec<u8, Msb0>>) -> Vec<u8>;
+
+    /// Reads the (symbol, code length) pairs written by `write_lengths`.
+    fn read_lengths(reader: &mut ByteReader) -> Result<Vec<(Self, usize)>, Error>;
+}
+
+/// Error of the character methods on content which isn't UTF-8 text.
+fn not_text(err: std::str::Utf8Error) -> Error {
+    Error::InvalidArgument(format!("Content is not UTF-8 text ({}), use the huffman-bytes method for binary files.", err))
+}
+
+impl HuffmanSymbol for char {
+    fn split(content: &[u8]) -> Result<Vec<char>, Error> {
+        Ok(str::from_utf8(content).map_err(not_text)?.chars().collect())
+    }
+
+    fn complete_len(content: &[u8]) -> Result<usize, Error> {
+        match str::from_utf8(content) {
+            Ok(_) => Ok(content.len()),
+            // No error length means the content ends in the middle of a character
+            Err(err) if err.error_len().is_none() => Ok(err.valid_up_to()),
+            Err(err) => Err(not_text(err)),
         }
     }
-    
-    // This part writes the name and content of the file 
-    let mut encoded: BitVec<u8, Msb0> = BitVec::new();
-    for file in file_paths {
-        println!("Encoding {} in {}", file, &config.archive_name);
-        let file_content = read_to_string(Path::new(file))?;
-        //adds name
-        for ch in file.chars() {
-            let code = code_map.get(&ch).ok_or(format!("Missing character in code map: {}", ch))?;
-            encoded.extend_from_bitslice(code);
+
+    fn join(symbols: Vec<char>) -> Vec<u8> {
+        symbols.into_iter().collect::<String>().into_bytes()
+    }
+
+    fn byte_len(self) -> u64 {
+        self.len_utf8() as u64
+    }
+
+    fn from_byte(byte: u8) -> char {
+        char::from(byte)
+    }
+
+    fn write_lengths(code_map: &HashMap<char, BitVec<u8, Msb0>>) -> Vec<u8> {
+        write_char_lengths(code_map)
+    }
+
+    fn read_lengths(reader: &mut ByteReader) -> Result<Vec<(char, usize)>, Error> {
+        read_char_lengths(reader)
+    }
+}
+
+impl HuffmanSymbol for u8 {
+    fn split(content: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(content.to_vec())
+    }
+
+    fn complete_len(content: &[u8]) -> Result<usize, Error> {
+        Ok(content.len())
+    }
+
+    fn join(symbols: Vec<u8>) -> Vec<u8> {
+        symbols
+    }
+
+    fn byte_len(self) -> u64 {
+        1
+    }
+
+    fn from_byte(byte: u8) -> u8 {
+        byte
+    }
+
+    fn write_lengths(code_map: &HashMap<u8, BitVec<u8, Msb0>>) -> Vec<u8> {
+        write_byte_lengths(code_map)
+    }
+
+    fn read_lengths(reader: &mut ByteReader) -> Result<Vec<(u8, usize)>, Error> {
+        read_byte_lengths(reader)
+    }
+}
+
+
+/// Where the code tables of a Huffman archive are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableMode {
+    /// One table built from every file, stored in the method header
+    Shared,
+    /// Tables stored with each file: one per block of the file (see `split_blocks`)
+    PerFile,
+    /// Each block of each file uses its own table or the shared one, whichever gives the smallest archive. The shared
+    /// table may be a preset, when one fits the files better than a table built on them
+    Auto,
+    /// Blocks use a built-in table, the method header only holding its id. Blocks with symbols the table has no code
+    /// for get their own table
+    Preset(Preset),
+}
+
+impl TableMode {
+    /// Returns the table mode matching the name given with the `-t` option.
+    pub fn from_name(name: &str) -> Option<TableMode> {
+        match name {
+            "shared" => Some(TableMode::Shared),
+            "per-file" => Some(TableMode::PerFile),
+            "auto" => Some(TableMode::Auto),
+            name => Preset::from_name(name).map(TableMode::Preset),
         }
+    }
+}
+
+/// First byte of the method header of Huffman archives, telling if a shared table follows.
+const SHARED_TABLE: u8 = 0;
+const PER_FILE_TABLES: u8 = 1;
+/// The shared table is a preset, whose id (u8) follows
+const PRESET_TABLE: u8 = 2;
+
+/// First byte of each block, telling which table the block is encoded with.
+const BLOCK_SHARED_TABLE: u8 = 0;
+const BLOCK_OWN_TABLE: u8 = 1;
+const BLOCK_END: u8 = 2;
+
+/// Number of symbols of the chunks a file is cut into, before they are gathered into blocks.
+const BLOCK_CHUNK_SYMBOLS: usize = 8192;
+
+
+/// Returns a `Hashmap` with every symbol of `symbols` as key and its number of occurence as value.
+pub fn symbol_parser<S: Copy + Eq + Hash>(symbols: &[S]) -> HashMap<S, u64> {
+    let mut frequency_map = HashMap::new();
+    for &symbol in symbols {
+        *frequency_map.entry(symbol).or_insert(0) += 1;
+    }
+    frequency_map
+}
+
+
+/// Returns the number of bits needed to encode symbols with these frequencies using `code_map`, or `None` if some of
+/// the symbols have no code in it.
+pub fn encoded_bits_len<S: Eq + Hash>(frequencies: &HashMap<S, u64>, code_map: &HashMap<S, BitVec<u8, Msb0>>) -> Option<u64> {
+    frequencies.iter()
+        .map(|(symbol, &count)| code_map.get(symbol).map(|code| count * code.len() as u64))
+        .sum()
+}
+
+
+/// Size in bytes of a bitstream of `bits` bits written by `BitWriter::finish`.
+fn stream_size(bits: u64) -> u64 {
+    8 + bits.div_ceil(8)
+}
+
+
+/// Builds the canonical code map of the symbols of `frequencies`, no code being longer than `max_code_length` bits.
+pub fn build_canonical_bitvec_map_from_frequencies<S: Copy + Ord + Hash>(frequencies: &HashMap<S, u64>, max_code_length: Option<usize>) -> Result<HashMap<S, BitVec<u8, Msb0>>, Error> {
+    let mut freq_vec: Vec<(S, u64)> = frequencies.iter().map(|(&s, &f)| (s, f)).collect();
+    freq_vec.sort_unstable();
+    let lengths = code_lengths(&freq_vec, max_code_length)?;
+    Ok(build_canonical_code_from_lengths(&lengths))
+}
+
+
+// ---------- Blocks ---------
+// A Huffman stream (the payload of each file) is a sequence of blocks, each one starting with its `table kind` (u8).
+// A block of kind `BLOCK_END` ends the stream. Other blocks go on with `block length` (varint, in bytes), then the code
+// lengths of their own table if they have one, `original length` (varint) and their bitstream.
+
 
-        // Add separator (null character) between name and file content
-        let separator_code = code_map.get(&separator).ok_or("Missing separator in code map")?;
-        encoded.extend_from_bitslice(separator_code);
+/// Code map of a Huffman table, from symbols to their codes.
+pub type CodeMap<S> = HashMap<S, BitVec<u8, Msb0>>;
 
-        //adds content
-        for ch in file_content.chars() {
-            let code = code_map.get(&ch).ok_or(format!("Missing character in code map: {}", ch))?;
-            encoded.extend_from_bitslice(code);
+
+/// Table a block is encoded with.
+pub enum BlockTable<S> {
+    Shared,
+    Own(CodeMap<S>),
+}
+
+/// Block of a file: a range of its symbols encoded with the same table.
+pub struct Block<S> {
+    pub range: Range<usize>,
+    pub table: BlockTable<S>,
+}
+
+
+/// Table built for a single block, with the estimated size of the block encoded with it, table included.
+struct OwnTable<S> {
+    cost: u64,
+    code_map: CodeMap<S>,
+}
+
+
+/// Returns the own table of a block with these symbol frequencies.
+fn own_table_cost<S: HuffmanSymbol>(frequencies: &HashMap<S, u64>, max_code_length: Option<usize>) -> Result<OwnTable<S>, Error> {
+    let code_map = build_canonical_bitvec_map_from_frequencies(frequencies, max_code_length)?;
+    let cost = S::write_lengths(&code_map).len() as u64
+        + block_cost(frequencies, &code_map).expect("a table has a code for the symbols it was built from");
+    Ok(OwnTable { cost, code_map })
+}
+
+
+/// Estimated size of a block with these symbol frequencies encoded with `code_map`, table excluded, or `None` if
+/// `code_map` can't encode the block.
+fn block_cost<S: HuffmanSymbol>(frequencies: &HashMap<S, u64>, code_map: &CodeMap<S>) -> Option<u64> {
+    let original_len: u64 = frequencies.iter().map(|(symbol, &count)| count * symbol.byte_len()).sum();
+    Some(1 + varint_size(original_len) as u64 + stream_size(encoded_bits_len(frequencies, code_map)?))
+}
+
+
+/// Cuts `symbols` into blocks which each get their own table, and returns their ranges.
+/// 
+/// The symbols are first cut into chunks of `BLOCK_CHUNK_SYMBOLS`. Going from left to right, each chunk either
+/// extends the current block or starts a new one, whichever gives the smallest estimated size: a new block pays for
+/// a new table, but its codes follow its own statistics.
+pub fn split_blocks<S: HuffmanSymbol>(symbols: &[S], max_code_length: Option<usize>) -> Result<Vec<Range<usize>>, Error> {
+    let mut blocks = Vec::new();
+    if symbols.is_empty() {
+        return Ok(blocks);
+    }
+
+    let mut block_start = 0;
+    let mut block_frequencies = symbol_parser(&symbols[..BLOCK_CHUNK_SYMBOLS.min(symbols.len())]);
+    let mut block_cost = own_table_cost(&block_frequencies, max_code_length)?.cost;
+
+    for chunk_start in (BLOCK_CHUNK_SYMBOLS..symbols.len()).step_by(BLOCK_CHUNK_SYMBOLS) {
+        let chunk = &symbols[chunk_start..(chunk_start + BLOCK_CHUNK_SYMBOLS).min(symbols.len())];
+        let chunk_frequencies = symbol_parser(chunk);
+        let chunk_cost = own_table_cost(&chunk_frequencies, max_code_length)?.cost;
+
+        let mut merged_frequencies = block_frequencies.clone();
+        for (&symbol, &count) in &chunk_frequencies {
+            *merged_frequencies.entry(symbol).or_insert(0) += count;
+        }
+        let merged_cost = own_table_cost(&merged_frequencies, max_code_length)?.cost;
+
+        if block_cost + chunk_cost < merged_cost {
+            blocks.push(block_start..chunk_start);
+            block_start = chunk_start;
+            block_frequencies = chunk_frequencies;
+            block_cost = chunk_cost;
+        } else {
+            block_frequencies = merged_frequencies;
+            block_cost = merged_cost;
         }
+    }
+    blocks.push(block_start..symbols.len());
+
+    Ok(blocks)
+}
 
-        // Adds sepatator between file content and next file name
-        encoded.extend_from_bitslice(separator_code);
 
+/// Returns the blocks of a file and the table each one is encoded with, following the table mode.
+/// `shared_map` is only used when the mode allows it, and when it has a code for every symbol of the block: the
+/// others get their own table, even with `TableMode::Shared` and `TableMode::Preset`.
+fn plan_blocks<S: HuffmanSymbol>(symbols: &[S], shared_map: &CodeMap<S>, table_mode: TableMode, max_code_length: Option<usize>) -> Result<Vec<Block<S>>, Error> {
+    if symbols.is_empty() {
+        return Ok(Vec::new());
+    }
+    if matches!(table_mode, TableMode::Shared | TableMode::Preset(_)) && block_cost(&symbol_parser(symbols), shared_map).is_some() {
+        return Ok(vec![Block { range: 0..symbols.len(), table: BlockTable::Shared }]);
     }
 
-    //fs::write(write_path, encoded.clone().into_vec())?;
-    write_file.write_all( &encoded.clone().into_vec())?;
-    Ok(())    
+    let mut blocks = Vec::new();
+    for range in split_blocks(symbols, max_code_length)? {
+        let frequencies = symbol_parser(&symbols[range.clone()]);
+        let own = own_table_cost(&frequencies, max_code_length)?;
+
+        let table = if table_mode == TableMode::Auto && block_cost(&frequencies, shared_map).is_some_and(|cost| cost <= own.cost) {
+            BlockTable::Shared
+        } else {
+            BlockTable::Own(own.code_map)
+        };
+        blocks.push(Block { range, table });
+    }
+
+    Ok(blocks)
 }
 
 
-// ------- Decoder --------
+/// Writes the blocks of `symbols` in `writer`, each one with its own table if it has one, its original length and its
+/// bitstream. The end of the stream is written separately.
+fn write_blocks<S: HuffmanSymbol>(writer: &mut impl Write, symbols: &[S], blocks: &[Block<S>], shared_map: &CodeMap<S>) -> Result<(), Error> {
+    for block in blocks {
+        let block_symbols = &symbols[block.range.clone()];
+        let mut body = Vec::new();
+        let (kind, code_map) = match &block.table {
+            BlockTable::Shared => (BLOCK_SHARED_TABLE, shared_map),
+            BlockTable::Own(code_map) => {
+                body.extend_from_slice(&S::write_lengths(code_map));
+                (BLOCK_OWN_TABLE, code_map)
+            },
+        };
+
+        write_varint(&mut body, block_symbols.iter().map(|&symbol| symbol.byte_len()).sum());
+        body.extend_from_slice(&encode_symbols(block_symbols.iter().copied(), code_map)?);
+
+        let mut header = vec![kind];
+        write_varint(&mut header, body.len() as u64);
+        writer.write_all(&header)?;
+        writer.write_all(&body)?;
+    }
 
+    Ok(())
+}
 
-/// Main decoding function which takes the Config struct and decompresses the files in it.
-/// Creates a subfolder for each archive, and puts its decompressed files in it.
+
+// ---------- Archive encoder ---------
+
+
+/// Returns the archive of the files `files`, given as (name, content) pairs, using the Huffman coding algorithm on the
+/// symbols `S`.
 /// 
-/// For each archive, the function :
-/// - reads it and extracts its map size
-/// - extracts its character-to-symbol map
-/// - decompresses the file name and file content, separated by a null character
-/// - writes each decompressed file in a subfolder with the same name as the archive
-pub fn decode_bitvec_huffman(config: &super::Config) -> Result<(), Box<dyn Error>> {
-    for archive in &config.files {
-        
-        let archive_name = extract_file_from_path(&archive)?;
-        if !Path::new(&archive_name).exists() {
-            fs::create_dir(&archive_name)?;
+/// This function:
+/// - builds the code map shared by all files, or takes the one of a preset
+/// - cuts each file into blocks and picks the table of each block, following the table mode (see `TableMode`)
+/// - writes the method header: `table mode` (u8), followed by the code lengths of the shared map if blocks can use it,
+///   or by the id of the preset (u8)
+/// - encodes each file in its own payload with a `HuffmanEncoder`
+/// 
+/// Empty files are stored without any block.
+pub fn build_huffman_archive<S: HuffmanSymbol>(files: &[NamedFile], method: EncodingMethod, table_mode: TableMode, max_code_length: Option<usize>) -> Result<Archive, Error> {
+    let mut contents: Vec<Vec<S>> = Vec::new();
+    for (_, content) in files {
+        contents.push(S::split(content)?);
+    }
+
+    // Merges the frequencies of all files to have a complete, unique symbol-to-code map
+    let mut merged_frequencies: HashMap<S, u64> = HashMap::new();
+    for symbols in &contents {
+        for &symbol in symbols {
+            *merged_frequencies.entry(symbol).or_insert(0) += 1;
+        }
+    }
+    let shared_map = build_canonical_bitvec_map_from_frequencies(&merged_frequencies, max_code_length)?;
+    let shared_header = S::write_lengths(&shared_map);
+
+    // Encodes the content of each file as a stream, with or without the shared table
+    let encode = |shared_map: Option<&CodeMap<S>>, table_mode: TableMode| -> Result<Vec<Vec<u8>>, Error> {
+        let mut payloads = Vec::new();
+        for (_, content) in files {
+            let mut encoder = HuffmanEncoder::<_, S>::with_table(Vec::new(), shared_map.cloned())
+                .table_mode(table_mode)
+                .max_code_length(max_code_length);
+            encoder.write_all(content)?;
+            payloads.push(encoder.finish()?);
         }
+        Ok(payloads)
+    };
 
-        let bytes = fs::read(Path::new(&archive))?;
-        let mut cursor = 0;
+    let (method_header, payloads) = match table_mode {
+        TableMode::PerFile => (vec![PER_FILE_TABLES], encode(None, table_mode)?),
+        TableMode::Preset(preset) => {
+            let preset_map = build_canonical_code_from_lengths(&preset.lengths::<S>());
+            (vec![PRESET_TABLE, preset.id()], encode(Some(&preset_map), table_mode)?)
+        },
+        TableMode::Shared | TableMode::Auto => {
+            let mut header = vec![SHARED_TABLE];
+            header.extend_from_slice(&shared_header);
+            let mut payloads = encode(Some(&shared_map), table_mode)?;
+
+            // In auto mode, the shared table is only kept if it saves more than it costs, and is replaced by the preset
+            // which fits the files best if that saves even more
+            if table_mode == TableMode::Auto {
+                let size = |header: &[u8], payloads: &[Vec<u8>]| header.len() + payloads.iter().map(Vec::len).sum::<usize>();
+
+                let own_payloads = encode(None, table_mode)?;
+                if size(&[PER_FILE_TABLES], &own_payloads) < size(&header, &payloads) {
+                    header = vec![PER_FILE_TABLES];
+                    payloads = own_payloads;
+                }
 
-        // First byte = number of entries in the code map
-        let map_size = bytes[cursor] as usize;
-        cursor += 1;
+                if let Some((preset, preset_map)) = best_preset(&merged_frequencies) {
+                    let preset_header = vec![PRESET_TABLE, preset.id()];
+                    let preset_payloads = encode(Some(&preset_map), table_mode)?;
+                    if size(&preset_header, &preset_payloads) < size(&header, &payloads) {
+                        header = preset_header;
+                        payloads = preset_payloads;
+                    }
+                }
+            }
+            (header, payloads)
+        },
+    };
 
-        let mut bit_map: HashMap<BitVec<u8, Msb0>, char> = HashMap::new();
+    let mut members = Vec::new();
+    for (((name, _), symbols), payload) in files.iter().zip(&contents).zip(payloads) {
+        members.push(Member {
+            name: name.clone(),
+            original_len: symbols.iter().map(|&symbol| symbol.byte_len()).sum(),
+            payload,
+        });
+    }
 
-        for _ in 0..map_size {
-            // Step 1: Decode char (1–4 UTF-8 bytes)
-            let first_byte = bytes[cursor];
-            let char_len = match first_byte {
-                0x00..=0x7F => 1,             // ASCII
-                0xC0..=0xDF => 2,             // 2-byte UTF-8
-                0xE0..=0xEF => 3,             // 3-byte UTF-8
-                0xF0..=0xF7 => 4,             // 4-byte UTF-8
-                _ => return Err("Invalid UTF-8 character prefix in Huffman map".into()),
-            };
+    Ok(Archive { method, method_header, members })
+}
 
-            let ch_bytes = &bytes[cursor..cursor + char_len];
-            let ch = str::from_utf8(ch_bytes)?.chars().next().unwrap();
-            cursor += char_len;
 
-            // Step 2: Get bit length
-            let bit_len = bytes[cursor] as usize;
-            cursor += 1;
+/// Returns the canonical code map of `preset`, or `None` if it has no code for some of the symbols of `frequencies`.
+pub fn preset_code_map<S: HuffmanSymbol>(preset: Preset, frequencies: &HashMap<S, u64>) -> Option<CodeMap<S>> {
+    let code_map = build_canonical_code_from_lengths(&preset.lengths::<S>());
+    frequencies.keys().all(|symbol| code_map.contains_key(symbol)).then_some(code_map)
+}
 
-            // Step 3: Get the byte slice for the code
-            let byte_len = (bit_len + 7) / 8;
-            let code_bytes = &bytes[cursor..cursor + byte_len];
-            cursor += byte_len;
 
-            let bits = BitVec::<u8, Msb0>::from_slice(code_bytes)[..bit_len].to_bitvec();
-            bit_map.insert(bits, ch);
+/// Returns the preset giving the fewest bits to symbols with these frequencies, among the presets having a code for all
+/// of them.
+fn best_preset<S: HuffmanSymbol>(frequencies: &HashMap<S, u64>) -> Option<(Preset, CodeMap<S>)> {
+    let mut best: Option<(u64, Preset, CodeMap<S>)> = None;
+    for preset in Preset::ALL {
+        let Some(code_map) = preset_code_map(preset, frequencies) else { continue };
+        let Some(bits) = encoded_bits_len(frequencies, &code_map) else { continue };
+        if best.as_ref().is_none_or(|(best_bits, _, _)| bits < *best_bits) {
+            best = Some((bits, preset, code_map));
         }
+    }
+    best.map(|(_, preset, code_map)| (preset, code_map))
+}
+
+
+/// Returns the archive of the files `files`, given as (name, content) pairs, using the Huffman coding algorithm on the
+/// characters of the files, which must be UTF-8 text. See `build_huffman_archive`.
+pub fn encode_bitvec_huffman(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    build_huffman_archive::<char>(files, EncodingMethod::Huffman, options.table_mode, options.max_code_length)
+}
+
+
+// ------- Decoder --------
+
+
+/// Number of bits used to index the lookup table of `CanonicalDecoder`. Codes longer than that go through the slow path.
+const LOOKUP_BITS: usize = 10;
+
+
+/// Decoder for canonical Huffman codes, built from the code length of each symbol only.
+/// 
+/// The next `LOOKUP_BITS` bits of the stream index a table giving the symbol and its code length directly, so most symbols
+/// are decoded with a single lookup. Longer codes are decoded bit by bit using the first code of each length, which is
+/// enough because canonical codes of the same length are consecutive integers.
+#[derive(Clone)]
+pub struct CanonicalDecoder<S> {
+    /// Symbols sorted in canonical order: by code length, then by symbol
+    symbols: Vec<S>,
+    /// For each code length: first code of that length, index in `symbols` of its symbol, number of codes of that length
+    first_code: Vec<u64>,
+    first_index: Vec<usize>,
+    count: Vec<usize>,
+    /// Lookup table entries are (index in `symbols`, code length), a length of 0 meaning the code is longer than the table
+    table: Vec<(usize, usize)>,
+    table_bits: usize,
+}
+
+impl<S: Copy + Ord> CanonicalDecoder<S> {
+    /// Builds the decoder from (symbol, code length) pairs, as produced by `build_canonical_code_bitvec`.
+    /// Returns an error if the lengths can't come from a prefix code.
+    pub fn from_lengths(lengths: &[(S, usize)]) -> Result<CanonicalDecoder<S>, Error> {
+        let mut sorted = lengths.to_vec();
+        sorted.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
 
-        if DEBUG {
-            println!("[DEBUG]Huffman map:");
-            for (bits, ch) in &bit_map {
-                println!("[DEBUG]  {:?} → '{}'", bits, ch);
+        let max_len = sorted.last().map_or(0, |&(_, len)| len);
+        if max_len > 64 {
+            return Err(Error::corrupt("Huffman code longer than 64 bits"));
+        }
+
+        let mut count = vec![0; max_len + 1];
+        for &(_, len) in &sorted {
+            count[len] += 1;
+        }
+
+        // Same numbering as `build_canonical_code_bitvec`: codes of a length follow the last code of the previous length, shifted
+        let mut first_code = vec![0u64; max_len + 1];
+        let mut first_index = vec![0; max_len + 1];
+        let mut code: u128 = 0;
+        let mut index = count.first().copied().unwrap_or(0);
+        for len in 1..=max_len {
+            if code + count[len] as u128 > 1 << len {
+                return Err(Error::corrupt("code lengths don't form a valid prefix code"));
             }
+            first_code[len] = code as u64;
+            first_index[len] = index;
+            code = (code + count[len] as u128) << 1;
+            index += count[len];
         }
 
-        if bit_map.len() <= 1 {
-            return Err("Decoding map is empty.".into());
+        let table_bits = max_len.min(LOOKUP_BITS);
+        let mut table = vec![(0, 0); 1 << table_bits];
+        for len in 1..=table_bits {
+            for rank in 0..count[len] {
+                let code = (first_code[len] + rank as u64) as usize;
+                let shift = table_bits - len;
+                for entry in &mut table[code << shift..(code + 1) << shift] {
+                    *entry = (first_index[len] + rank, len);
+                }
+            }
         }
 
-        let bitstream = BitVec::<u8, Msb0>::from_slice(&bytes[cursor..]);
+        Ok(CanonicalDecoder {
+            symbols: sorted.into_iter().map(|(symbol, _)| symbol).collect(),
+            first_code,
+            first_index,
+            count,
+            table,
+            table_bits,
+        })
+    }
 
-        let mut file_name = String::new();
-        let mut content = String::new();
-        let mut buffer = BitVec::<u8, Msb0>::new();
-        
+    /// Reads the next code from the stream and returns its symbol.
+    pub fn decode_symbol(&self, reader: &mut BitReader) -> Result<S, Error> {
+        // Fast path: one lookup for codes up to `table_bits` long
+        let (bits, available) = reader.peek_bits(self.table_bits);
+        let (index, len) = self.table[bits as usize];
+        if len > 0 && len <= available {
+            reader.skip(len);
+            return Ok(self.symbols[index]);
+        }
 
-        let mut reading_name = true;
-        let mut file_names = Vec::new();
-        let mut file_contents = Vec::new();
+        // Slow path: extends the code bit by bit until it falls in the range of codes of its length
+        let mut code: u64 = 0;
+        for len in 1..self.count.len() {
+            code = (code << 1) | reader.read_bit()? as u64;
+            let rank = code.wrapping_sub(self.first_code[len]);
+            if code >= self.first_code[len] && rank < self.count[len] as u64 {
+                return Ok(self.symbols[self.first_index[len] + rank as usize]);
+            }
+        }
 
-        
+        Err(Error::corrupt("invalid Huffman code in bitstream"))
+    }
+}
 
-        for bit in bitstream {
-            buffer.push(bit);
 
+/// Decodes symbols from the bitstream `payload` until they add up to `original_len` bytes, `symbol_len` giving the number
+/// of bytes each symbol stands for.
+/// 
+/// The decoder stops at the exact bit length recorded in the payload: a stream ending in the middle of a code or before
+/// the end of the file is reported as truncated, and bits left after the last symbol are reported as well.
+pub fn decode_symbols<S: Copy + Ord>(payload: &[u8], decoder: &CanonicalDecoder<S>, original_len: u64, symbol_len: impl Fn(S) -> u64) -> Result<Vec<S>, Error> {
+    let mut reader = BitReader::new(payload)?;
+    let mut symbols = Vec::new();
+    let mut decoded_len = 0;
+
+    while decoded_len < original_len {
+        let symbol = decoder.decode_symbol(&mut reader)?;
+        symbols.push(symbol);
+        decoded_len += symbol_len(symbol);
+    }
 
-            // Try decoding buffer
-            for i in 1..=buffer.len() {
-                let prefix = buffer[..i].to_bitvec();
-                if let Some(&ch) = bit_map.get(&prefix) {
-                    if ch == '\u{0000}' && reading_name {
-                        buffer.drain(..i);
-                        reading_name = false;
-                        continue;
-                    } 
-                    else if ch == '\u{0000}' && !reading_name {
-                        buffer.clear();
-                        reading_name = true;
-                        file_names.push(file_name.clone());
-                        file_contents.push(content.clone());
-                        file_name.clear();
-                        content.clear();
-                        continue;
-                    }
-                    else if reading_name {
-                        file_name.push(ch);
-                    } 
-                    else {
-                        content.push(ch);
-                    }
-                    buffer.drain(..i);
-                    break;
-                } 
-            }
+    if decoded_len != original_len {
+        return Err(Error::corrupt("decoded file is longer than its original length"));
+    }
+    if reader.remaining() != 0 {
+        return Err(Error::corrupt(format!("{} bits left after the end of the file", reader.remaining())));
+    }
+
+    Ok(symbols)
+}
+
+
+/// Reads code lengths with `S::read_lengths` and returns the decoder of the canonical codes they stand for.
+fn read_decoder<S: HuffmanSymbol>(reader: &mut ByteReader) -> Result<CanonicalDecoder<S>, Error> {
+    let lengths = S::read_lengths(reader)?;
+
+    // The codes are rebuilt from their lengths
+    CanonicalDecoder::from_lengths(&lengths)
+}
+
+
+/// Main decoding function for archives written by `build_huffman_archive`, returning the content of each of their files.
+/// 
+/// The function :
+/// - reads the table mode and, if there is a shared table, its code lengths in the method header
+/// - decodes the stream of each file with a `HuffmanDecoder`, which rebuilds the canonical codes of the blocks which have
+///   their own table
+pub fn decode_huffman_archive<S: HuffmanSymbol>(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+
+    let shared_decoder = match reader.read_u8()? {
+        SHARED_TABLE => Some(read_decoder::<S>(&mut reader)?),
+        PER_FILE_TABLES => None,
+        PRESET_TABLE => {
+            let id = reader.read_u8()?;
+            let preset = Preset::from_id(id).ok_or(Error::corrupt_at(1, format!("unknown preset table {}", id)))?;
+            Some(CanonicalDecoder::from_lengths(&preset.lengths::<S>())?)
+        },
+        mode => return Err(Error::corrupt_at(0, format!("unknown Huffman table mode {}", mode))),
+    };
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let mut decoder = HuffmanDecoder::<_, S>::with_table(&member.payload[..], shared_decoder.clone());
+        let mut content = Vec::new();
+        decoder.read_to_end(&mut content)?;
+
+        if !decoder.into_inner().is_empty() {
+            return Err(Error::corrupt(format!("unexpected data after the end of {}", member.name)));
+        }
+        if content.len() as u64 != member.original_len {
+            return Err(Error::corrupt(format!("{} doesn't have its original length", member.name)));
         }
 
-        
+        contents.push(content);
+    }
+
+    Ok(contents)
+}
+
+
+/// Decodes archives written by `encode_bitvec_huffman`, returning the content of each of their files.
+pub fn decode_bitvec_huffman(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    decode_huffman_archive::<char>(archive)
+}
+
+
+
+
+
+// #################################
+// ------- Streams -----------------
+// #################################
+
+/// Number of bytes an encoder gathers before cutting them into blocks (see `split_blocks`) and writing them.
+const STREAM_SEGMENT_BYTES: usize = 1 << 20;
+
+
+/// Huffman encoder wrapping a writer: the bytes written to it are written compressed to the inner writer, as a stream
+/// of blocks (see `write_blocks`). Nothing about the content has to be known beforehand.
+/// 
+/// The encoder gathers `STREAM_SEGMENT_BYTES` bytes (or less, when flushed) before encoding them, each segment being cut
+/// into blocks which get their own table. With a shared table, which the decoder must be given as well, blocks use
+/// their own table or the shared one following the table mode. `finish` must be called to end the stream.
+/// 
+/// The symbols are the 256 byte values by default, or the characters of UTF-8 text with `S = char`.
+pub struct HuffmanEncoder<W: Write, S: HuffmanSymbol = u8> {
+    inner: W,
+    shared_map: Option<CodeMap<S>>,
+    table_mode: TableMode,
+    max_code_length: Option<usize>,
+    buffer: Vec<u8>,
+}
 
-        // Checks if there are as many file names as file contents
-        if file_names.len() != file_contents.len() {
-            return Err("Mismatch between number of file names and contents.".into());
+impl<W: Write> HuffmanEncoder<W> {
+    /// Encoder on the 256 byte values, each block having its own table.
+    pub fn new(inner: W) -> HuffmanEncoder<W> {
+        HuffmanEncoder::with_table(inner, None)
+    }
+}
+
+impl<W: Write, S: HuffmanSymbol> HuffmanEncoder<W, S> {
+    /// Encoder which can use the canonical code map `shared_map` (as built by `build_canonical_bitvec_map_from_frequencies`)
+    /// instead of storing a table in a block. Blocks with symbols it has no code for always get their own table.
+    pub fn with_table(inner: W, shared_map: Option<CodeMap<S>>) -> HuffmanEncoder<W, S> {
+        HuffmanEncoder {
+            inner,
+            shared_map,
+            table_mode: TableMode::Auto,
+            max_code_length: Some(DEFAULT_MAX_CODE_LENGTH),
+            buffer: Vec::new(),
         }
+    }
 
-       
+    /// Sets how blocks pick their table when there is a shared table (`TableMode::Auto` by default). Without one, every
+    /// block has its own table.
+    pub fn table_mode(mut self, table_mode: TableMode) -> HuffmanEncoder<W, S> {
+        self.table_mode = table_mode;
+        self
+    }
 
-        // Writes the files
-        for (file_name, content) in file_names.into_iter().zip(file_contents.into_iter()) {
-            let name: &str;
-                match file_name.rsplit_once('/')  {
-                    None => name = &file_name,
-                    Some(n) => name = n.1,
-                }
-            let full_path = format!("{}/{}", archive_name, name);
-            fs::write(&full_path, content)?;
-            println!("Decompressed {}", &name);
+    /// Sets the maximum length of the codes of the tables built by the encoder, `None` for no limit.
+    pub fn max_code_length(mut self, max_code_length: Option<usize>) -> HuffmanEncoder<W, S> {
+        self.max_code_length = max_code_length;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Encodes the whole symbols of the buffer and writes their blocks. A symbol cut by the end of the buffer stays in it.
+    fn write_segment(&mut self) -> Result<(), Error> {
+        let complete_len = S::complete_len(&self.buffer)?;
+        let symbols = S::split(&self.buffer[..complete_len])?;
+
+        let empty_map = HashMap::new();
+        let (shared_map, table_mode) = match &self.shared_map {
+            Some(shared_map) => (shared_map, self.table_mode),
+            None => (&empty_map, TableMode::PerFile),
+        };
+        let blocks = plan_blocks(&symbols, shared_map, table_mode, self.max_code_length)?;
+        write_blocks(&mut self.inner, &symbols, &blocks, shared_map)?;
+
+        self.buffer.drain(..complete_len);
+        Ok(())
+    }
+
+    /// Encodes what is left, ends the stream and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_segment()?;
+        if !self.buffer.is_empty() {
+            return Err(Error::InvalidArgument("Stream ends in the middle of a symbol".into()).into());
         }
 
-    
-        
+        self.inner.write_all(&[BLOCK_END])?;
+        self.inner.flush()?;
+        Ok(self.inner)
     }
+}
 
-    Ok(())
+impl<W: Write, S: HuffmanSymbol> Write for HuffmanEncoder<W, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Never holds more than a segment, the part of `buf` which doesn't fit is left to the next call
+        let count = buf.len().min(STREAM_SEGMENT_BYTES.saturating_sub(self.buffer.len()).max(1));
+        self.buffer.extend_from_slice(&buf[..count]);
+
+        if self.buffer.len() >= STREAM_SEGMENT_BYTES {
+            self.write_segment()?;
+        }
+        Ok(count)
+    }
+
+    /// Encodes the bytes written so far in blocks, so they can be decoded on the other side, and flushes the inner writer.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_segment()?;
+        self.inner.flush()
+    }
+}
+
+
+/// Huffman decoder wrapping a reader: the bytes read from it are the content of the stream written by a `HuffmanEncoder`
+/// on the inner reader. The decoder stops reading the inner reader after the end of the stream.
+pub struct HuffmanDecoder<R: Read, S: HuffmanSymbol = u8> {
+    inner: R,
+    shared_decoder: Option<CanonicalDecoder<S>>,
+    /// Decoded bytes of the current block, and how many of them were already read
+    block: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl<R: Read> HuffmanDecoder<R> {
+    /// Decoder on the 256 byte values, for streams written without a shared table.
+    pub fn new(inner: R) -> HuffmanDecoder<R> {
+        HuffmanDecoder::with_table(inner, None)
+    }
+}
+
+impl<R: Read, S: HuffmanSymbol> HuffmanDecoder<R, S> {
+    /// Decoder for streams written with a shared table, `shared_decoder` being the decoder of its code lengths.
+    pub fn with_table(inner: R, shared_decoder: Option<CanonicalDecoder<S>>) -> HuffmanDecoder<R, S> {
+        HuffmanDecoder { inner, shared_decoder, block: Vec::new(), pos: 0, finished: false }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the inner reader, positioned right after the end of the stream once everything was read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads one byte of the inner reader, the stream being truncated if there is none.
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut byte = [0];
+        match self.inner.read_exact(&mut byte) {
+            Ok(()) => Ok(byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(Error::corrupt("Huffman stream truncated")),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads and decodes the next block, or the end of the stream.
+    fn read_block(&mut self) -> Result<(), Error> {
+        let kind = self.read_byte()?;
+        if kind == BLOCK_END {
+            self.finished = true;
+            return Ok(());
+        }
+        if kind != BLOCK_SHARED_TABLE && kind != BLOCK_OWN_TABLE {
+            return Err(Error::corrupt(format!("unknown block table kind {}", kind)));
+        }
+
+        let block_len = read_varint_with(|| self.read_byte())?;
+
+        // Reading through `take` never allocates more than the inner reader actually holds
+        let mut body = Vec::new();
+        self.inner.by_ref().take(block_len).read_to_end(&mut body)?;
+        if body.len() as u64 != block_len {
+            return Err(Error::corrupt("Huffman stream truncated"));
+        }
+
+        let mut reader = ByteReader::new(&body);
+        let own_decoder;
+        let decoder = match kind {
+            BLOCK_SHARED_TABLE => self.shared_decoder.as_ref().ok_or(Error::corrupt("block uses a shared table, but there is none"))?,
+            _ => {
+                own_decoder = read_decoder::<S>(&mut reader)?;
+                &own_decoder
+            },
+        };
+
+        let original_len = reader.read_varint()?;
+        let stream = take_bitstream(&mut reader)?;
+        if reader.position() != body.len() {
+            return Err(Error::corrupt_at(reader.position(), "unexpected data at the end of a block"));
+        }
+
+        self.block = S::join(decode_symbols(stream, decoder, original_len, S::byte_len)?);
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read, S: HuffmanSymbol> Read for HuffmanDecoder<R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.block.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.read_block()?;
+        }
+
+        let count = buf.len().min(self.block.len() - self.pos);
+        buf[..count].copy_from_slice(&self.block[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+
+
+
+
+// #################################
+// ------- Byte functions ----------
+// #################################
+
+
+/// Returns the archive of the files `files`, using the Huffman coding algorithm on the 256 byte values.
+/// Unlike `encode_bitvec_huffman`, the files don't need to be text, so binary files (PDF, images, executables...) can be archived.
+pub fn encode_bitvec_huffman_bytes(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    build_huffman_archive::<u8>(files, EncodingMethod::HuffmanBytes, options.table_mode, options.max_code_length)
+}
+
+
+/// Decodes archives written by `encode_bitvec_huffman_bytes`, returning the raw bytes of each of their files.
+pub fn decode_bitvec_huffman_bytes(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    decode_huffman_archive::<u8>(archive)
 }
 
 
@@ -752,17 +1700,17 @@ pub fn decode_bitvec_huffman(config: &super::Config) -> Result<(), Box<dyn Error
 // #################################
 
 
-pub fn print_tree(tree: &Tree, indent: usize) {
+pub fn print_tree<S: Debug>(tree: &Tree<S>, node: usize, indent: usize) {
     let pad = "  ".repeat(indent);
 
-    match tree {
-        Tree::Leaf(ch, freq) => {
-            println!("{}Leaf('{}': {})", pad, ch, freq);
+    match &tree.nodes[node] {
+        Node::Leaf(ch, freq) => {
+            println!("{}Leaf({:?}: {})", pad, ch, freq);
         }
-        Tree::Branch(left, freq, right) => {
+        Node::Branch(left, freq, right) => {
             println!("{}Branch({})", pad, freq);
-            print_tree(left, indent + 1);
-            print_tree(right, indent + 1);
+            print_tree(tree, *left, indent + 1);
+            print_tree(tree, *right, indent + 1);
         }
     }
 }
@@ -784,13 +1732,333 @@ pub fn print_map<K: Debug, V: Debug>(map: &HashMap<K, V>) {
 }
 
 
-//#[cfg(test)]
-// mod tests {
-//     use super::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{read_archive, write_archive};
+
+    const TABLE_MODES: [TableMode; 5] = [
+        TableMode::Shared, TableMode::PerFile, TableMode::Auto, TableMode::Preset(Preset::English), TableMode::Preset(Preset::Uniform),
+    ];
+
+    /// Writes the archive of `files`, reads it back and checks that every file comes out unchanged.
+    fn round_trip<S: HuffmanSymbol>(files: &[(&str, &[u8])], method: EncodingMethod) {
+        let files: Vec<NamedFile> = files.iter().map(|&(name, content)| (name.to_string(), content.to_vec())).collect();
 
-//     #[test]
-//     fn it_works() {
-//         // let result = add(2, 2);
-//         // assert_eq!(result, 4);
-//     }
-// }
\ No newline at end of file
+        for table_mode in TABLE_MODES {
+            let archive = build_huffman_archive::<S>(&files, method, table_mode, Some(DEFAULT_MAX_CODE_LENGTH)).unwrap();
+            let archive = read_archive(&write_archive(&archive).unwrap()).unwrap();
+            let contents = decode_huffman_archive::<S>(&archive).unwrap();
+
+            assert_eq!(archive.members.len(), files.len());
+            for ((member, content), (name, original)) in archive.members.iter().zip(&contents).zip(&files) {
+                assert_eq!(&member.name, name, "{:?}", table_mode);
+                assert_eq!(content, original, "{:?}", table_mode);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_file_round_trips() {
+        round_trip::<char>(&[("__init__.py", b"")], EncodingMethod::Huffman);
+        round_trip::<u8>(&[("empty.bin", b"")], EncodingMethod::HuffmanBytes);
+    }
+
+    #[test]
+    fn empty_file_next_to_other_files_round_trips() {
+        round_trip::<char>(&[("a.txt", b"hello world"), ("__init__.py", b""), ("b.txt", b"abracadabra")], EncodingMethod::Huffman);
+        round_trip::<u8>(&[("empty.bin", b""), ("data.bin", &[0, 1, 2, 255, 255, 0])], EncodingMethod::HuffmanBytes);
+    }
+
+    #[test]
+    fn single_symbol_file_round_trips() {
+        round_trip::<char>(&[("spaces.txt", b"          ")], EncodingMethod::Huffman);
+        round_trip::<char>(&[("one.txt", "é".as_bytes())], EncodingMethod::Huffman);
+        round_trip::<u8>(&[("zeros.bin", &[0; 1000])], EncodingMethod::HuffmanBytes);
+    }
+
+    #[test]
+    fn single_symbol_files_with_different_symbols_round_trip() {
+        round_trip::<char>(&[("spaces.txt", b"     "), ("dots.txt", b"....."), ("empty.txt", b"")], EncodingMethod::Huffman);
+    }
+
+    #[test]
+    fn empty_name_round_trips() {
+        round_trip::<char>(&[("", b"no name"), ("", b"")], EncodingMethod::Huffman);
+    }
+
+    #[test]
+    fn header_holds_lengths_of_more_than_255_characters() {
+        // 300 ideographs (3 bytes in UTF-8) and two ASCII letters
+        let text: String = (0..300).map(|i| char::from_u32(0x4E00 + i).unwrap()).chain("aab".chars()).collect();
+        let frequencies: Vec<(char, u64)> = parser(&text).unwrap().into_iter().collect();
+        let mut lengths = code_lengths(&frequencies, None).unwrap();
+        let header = write_char_lengths(&build_canonical_code_from_lengths(&lengths));
+        assert_eq!(header.len(), 2 + 300 * 4 + 2 * 2);
+
+        let mut read_lengths = read_char_lengths(&mut ByteReader::new(&header)).unwrap();
+        lengths.sort();
+        read_lengths.sort();
+        assert_eq!(read_lengths, lengths);
+
+        // The presets only cover U+0000 to U+00FF
+        let files = vec![("cjk.txt".to_string(), text.into_bytes())];
+        for table_mode in [TableMode::Shared, TableMode::PerFile, TableMode::Auto] {
+            let bytes = write_archive(&build_huffman_archive::<char>(&files, EncodingMethod::Huffman, table_mode, None).unwrap()).unwrap();
+            let again = write_archive(&build_huffman_archive::<char>(&files, EncodingMethod::Huffman, table_mode, None).unwrap()).unwrap();
+            assert_eq!(bytes, again, "{:?}", table_mode);
+            assert_eq!(decode_huffman_archive::<char>(&read_archive(&bytes).unwrap()).unwrap()[0], files[0].1, "{:?}", table_mode);
+        }
+    }
+
+    #[test]
+    fn counts_over_65535_round_trip() {
+        // A byte repeated more times than a u16 can count
+        let files = vec![("zeros.bin".to_string(), [vec![0; 70_000], vec![1; 3]].concat())];
+        assert_eq!(byte_parser(&files[0].1)[&0], 70_000);
+        let archive = build_huffman_archive::<u8>(&files, EncodingMethod::HuffmanBytes, TableMode::PerFile, Some(DEFAULT_MAX_CODE_LENGTH)).unwrap();
+        assert_eq!(decode_huffman_archive::<u8>(&archive).unwrap()[0], files[0].1);
+
+        // More distinct characters than a u16 can count
+        let text: String = (0x10000..0x10000 + 70_000).filter_map(char::from_u32).collect();
+        let code_map = build_canonical_bitvec_map_from_string(&text, None).unwrap();
+        assert_eq!(code_map.len(), 70_000);
+        let lengths = read_char_lengths(&mut ByteReader::new(&write_char_lengths(&code_map))).unwrap();
+        assert_eq!(lengths.len(), 70_000);
+        assert!(lengths.iter().all(|(ch, len)| code_map[ch].len() == *len));
+    }
+
+    #[test]
+    fn small_files_use_a_preset_table() {
+        let files = vec![("config.json".to_string(), br#"{"name": "demo", "debug": true, "retries": 3}"#.to_vec())];
+        let archive = build_huffman_archive::<char>(&files, EncodingMethod::Huffman, TableMode::Auto, Some(DEFAULT_MAX_CODE_LENGTH)).unwrap();
+        assert_eq!(archive.method_header, vec![PRESET_TABLE, Preset::Json.id()]);
+        assert_eq!(decode_huffman_archive::<char>(&archive).unwrap()[0], files[0].1);
+
+        // Presets only cover the characters up to U+00FF
+        let files = vec![("dash.txt".to_string(), "a — b".as_bytes().to_vec())];
+        let archive = build_huffman_archive::<char>(&files, EncodingMethod::Huffman, TableMode::Auto, None).unwrap();
+        assert_ne!(archive.method_header[0], PRESET_TABLE);
+    }
+
+    #[test]
+    fn blocks_a_preset_does_not_cover_get_their_own_table() {
+        let files = vec![
+            ("ascii.txt".to_string(), b"plain text, nothing else".to_vec()),
+            ("dash.txt".to_string(), "a — b".as_bytes().to_vec()),
+        ];
+        let archive = build_huffman_archive::<char>(&files, EncodingMethod::Huffman, TableMode::Preset(Preset::English), None).unwrap();
+        assert_eq!(archive.method_header, vec![PRESET_TABLE, Preset::English.id()]);
+        assert_eq!(archive.members[0].payload[0], BLOCK_SHARED_TABLE);
+        assert_eq!(archive.members[1].payload[0], BLOCK_OWN_TABLE);
+
+        let contents = decode_huffman_archive::<char>(&archive).unwrap();
+        assert_eq!(contents, vec![files[0].1.clone(), files[1].1.clone()]);
+    }
+
+    #[test]
+    fn nibble_lengths_round_trip() {
+        let mut bytes = Vec::new();
+        write_nibble_lengths(&mut bytes, &[1, 15, 0, 7, 3]);
+        assert_eq!(bytes, vec![0x1F, 0x07, 0x30]);
+        assert_eq!(read_nibble_lengths(&mut ByteReader::new(&bytes), 5).unwrap(), vec![1, 15, 0, 7, 3]);
+        assert!(read_nibble_lengths(&mut ByteReader::new(&[0x1F, 0x07, 0x31]), 5).is_err());
+        assert_eq!(nibble_code_length(Some(20)), Some(MAX_NIBBLE_CODE_LENGTH));
+        assert_eq!(nibble_code_length(None), Some(MAX_NIBBLE_CODE_LENGTH));
+    }
+
+    #[test]
+    fn single_symbol_gets_one_bit_code() {
+        let lengths = code_lengths(&[('a', 42)], None).unwrap();
+        assert_eq!(lengths, vec![('a', 1)]);
+
+        let code_map = build_canonical_code_from_lengths(&lengths);
+        assert_eq!(code_map[&'a'].len(), 1);
+        assert!(code_lengths::<char>(&[], None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tree_gives_huffman_code_lengths() {
+        let tree = create_tree(&[('a', 5), ('b', 9), ('c', 12), ('d', 13), ('e', 16), ('f', 45)]);
+        assert!(matches!(tree.nodes[tree.root().unwrap()], Node::Branch(_, 100, _)));
+
+        let mut lengths = tree.code_lengths();
+        lengths.sort();
+        assert_eq!(lengths, vec![('a', 4), ('b', 4), ('c', 3), ('d', 3), ('e', 3), ('f', 1)]);
+
+        // The paths of the leaves have these lengths, and none is the prefix of another
+        let code_map = build_code_map(&tree);
+        assert!(lengths.iter().all(|(ch, len)| code_map[ch].len() == *len));
+        assert!(code_map.values().all(|code| code_map.values().filter(|other| other.starts_with(code)).count() == 1));
+
+        // Fibonacci frequencies give the deepest possible tree
+        let fibonacci: Vec<(u16, u64)> = (0..40u16).scan((1, 1), |(a, b), i| { let f = *a; *a = *b; *b += f; Some((i, f)) }).collect();
+        let max_len = create_tree(&fibonacci).code_lengths().into_iter().map(|(_, len)| len).max();
+        assert_eq!(max_len, Some(39));
+        assert!(create_tree::<u8>(&[]).root().is_none());
+    }
+
+    #[test]
+    fn package_merge_limits_code_lengths() {
+        // Sum of 2^-len over the codes, in units of 2^-max_len
+        let kraft = |lengths: &[(u16, usize)], max_len: usize| lengths.iter().map(|&(_, len)| 1u64 << (max_len - len)).sum::<u64>();
+
+        let fibonacci: Vec<(u16, u64)> = (0..40u16).scan((1, 1), |(a, b), i| { let f = *a; *a = *b; *b += f; Some((i, f)) }).collect();
+        let lengths = package_merge_lengths(&fibonacci, 15).unwrap();
+        assert_eq!(lengths.len(), 40);
+        assert!(lengths.iter().all(|&(_, len)| (1..=15).contains(&len)));
+        assert_eq!(kraft(&lengths, 15), 1 << 15);
+        assert_eq!(code_lengths(&fibonacci, Some(15)).unwrap(), lengths);
+
+        // 300 symbols don't fit in codes of 8 bits
+        let flat: Vec<(u16, u64)> = (0..300).map(|symbol| (symbol, 1)).collect();
+        assert!(package_merge_lengths(&flat, 8).is_err());
+        assert_eq!(kraft(&package_merge_lengths(&flat[..256], 8).unwrap(), 8), 1 << 8);
+
+        // `-l 1` is enough for two symbols
+        let files = vec![("ab.txt".to_string(), b"abbbbbbbbbbbbbbb".to_vec())];
+        for table_mode in [TableMode::Shared, TableMode::PerFile, TableMode::Auto] {
+            let archive = build_huffman_archive::<u8>(&files, EncodingMethod::HuffmanBytes, table_mode, Some(1)).unwrap();
+            assert_eq!(decode_huffman_archive::<u8>(&archive).unwrap()[0], files[0].1, "{:?}", table_mode);
+        }
+    }
+
+    #[test]
+    fn empty_table_decodes_nothing() {
+        let decoder = CanonicalDecoder::<u8>::from_lengths(&[]).unwrap();
+        assert!(decode_symbols(&BitWriter::new().finish(), &decoder, 0, |_| 1).unwrap().is_empty());
+
+        let mut writer = BitWriter::new();
+        writer.write_int(0, 1);
+        assert!(decode_symbols(&writer.finish(), &decoder, 1, |_| 1).is_err());
+    }
+
+    #[test]
+    fn codes_longer_than_the_lookup_table_decode() {
+        // Lengths 1, 2, ..., 19, 19: most codes go through the slow path of `decode_symbol`
+        let lengths: Vec<(u16, usize)> = (0..20).map(|symbol| (symbol, (symbol as usize + 1).min(19))).collect();
+        assert!(lengths.iter().filter(|&&(_, len)| len > LOOKUP_BITS).count() > 5);
+        let code_map = build_canonical_code_from_lengths(&lengths);
+        let decoder = CanonicalDecoder::from_lengths(&lengths).unwrap();
+
+        let symbols: Vec<u16> = (0..20).rev().chain(0..20).collect();
+        let mut writer = BitWriter::new();
+        for symbol in &symbols {
+            writer.write_bits(&code_map[symbol]);
+        }
+        let stream = writer.finish();
+        let mut reader = BitReader::new(&stream).unwrap();
+        let decoded: Vec<u16> = symbols.iter().map(|_| decoder.decode_symbol(&mut reader).unwrap()).collect();
+        assert_eq!(decoded, symbols);
+        assert_eq!(reader.remaining(), 0);
+
+        // A stream ending in the middle of a long code
+        let mut writer = BitWriter::new();
+        writer.write_bits(&code_map[&19][..15]);
+        let stream = writer.finish();
+        assert!(decoder.decode_symbol(&mut BitReader::new(&stream).unwrap()).is_err());
+    }
+
+    #[test]
+    fn blocks_only_use_a_shared_table_covering_them() {
+        let lengths = [(b'a', 1), (b'b', 2), (b'c', 2)];
+        let shared_map = build_canonical_code_from_lengths(&lengths);
+        let covered = b"abcabcaaaa".repeat(100);
+        let uncovered = [&covered[..], b"xyz"].concat();
+        assert_eq!(encoded_bits_len(&byte_parser(&covered), &shared_map), Some(1400));
+        assert_eq!(encoded_bits_len(&byte_parser(&uncovered), &shared_map), None);
+
+        let uses_shared = |content: &[u8], table_mode| plan_blocks(content, &shared_map, table_mode, Some(DEFAULT_MAX_CODE_LENGTH))
+            .unwrap().iter().any(|block| matches!(block.table, BlockTable::Shared));
+        for table_mode in TABLE_MODES {
+            assert_eq!(uses_shared(&covered, table_mode), table_mode != TableMode::PerFile, "{:?}", table_mode);
+            assert!(!uses_shared(&uncovered, table_mode), "{:?}", table_mode);
+
+            for content in [&covered, &uncovered] {
+                let mut encoder = HuffmanEncoder::with_table(Vec::new(), Some(shared_map.clone())).table_mode(table_mode);
+                encoder.write_all(content).unwrap();
+                let compressed = encoder.finish().unwrap();
+
+                let mut decoded = Vec::new();
+                let decoder = CanonicalDecoder::from_lengths(&lengths).unwrap();
+                HuffmanDecoder::with_table(&compressed[..], Some(decoder)).read_to_end(&mut decoded).unwrap();
+                assert_eq!(&decoded, content, "{:?}", table_mode);
+            }
+        }
+    }
+
+    #[test]
+    fn heterogeneous_content_is_split_into_blocks() {
+        // 64 KiB of English, then 64 KiB of pseudo-random bytes
+        let english = "It was the best of times, it was the worst of times, it was the age of wisdom. ".repeat(830);
+        let random = (0..65_536u32).scan(12345u32, |state, _| {
+            *state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            Some((*state >> 16) as u8)
+        });
+        let content: Vec<u8> = english.as_bytes()[..65_536].iter().copied().chain(random).collect();
+
+        assert!(split_blocks(&content, Some(DEFAULT_MAX_CODE_LENGTH)).unwrap().len() > 1);
+        let files = vec![("mixed.bin".to_string(), content)];
+        let size = |table_mode| {
+            let archive = build_huffman_archive::<u8>(&files, EncodingMethod::HuffmanBytes, table_mode, Some(DEFAULT_MAX_CODE_LENGTH)).unwrap();
+            assert_eq!(decode_huffman_archive::<u8>(&archive).unwrap()[0], files[0].1);
+            write_archive(&archive).unwrap().len()
+        };
+        let shared_size = size(TableMode::Shared);
+        assert!(size(TableMode::PerFile) < shared_size);
+        assert!(size(TableMode::Auto) < shared_size);
+
+        // Files ending right at, before and after the end of a chunk
+        for len in [BLOCK_CHUNK_SYMBOLS - 1, BLOCK_CHUNK_SYMBOLS, BLOCK_CHUNK_SYMBOLS + 1] {
+            round_trip::<u8>(&[("chunk.bin", &files[0].1[65_536 - len / 2..][..len])], EncodingMethod::HuffmanBytes);
+        }
+    }
+
+    #[test]
+    fn stream_round_trips_through_vec() {
+        let content: Vec<u8> = (0..STREAM_SEGMENT_BYTES + 1000).map(|i| (i % 7 * (i / 100_000)) as u8).collect();
+
+        let mut encoder = HuffmanEncoder::new(Vec::new());
+        for chunk in content.chunks(100_000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < content.len());
+
+        let mut decoder = HuffmanDecoder::new(&compressed[..]);
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, content);
+        assert!(decoder.into_inner().is_empty());
+    }
+
+    #[test]
+    fn char_stream_handles_characters_cut_between_writes() {
+        let text = "héllo wörld, 日本語のテキスト";
+        let mut encoder = HuffmanEncoder::<_, char>::with_table(Vec::new(), None);
+        for byte in text.as_bytes() {
+            encoder.write_all(&[*byte]).unwrap();
+            encoder.flush().unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoded = String::new();
+        HuffmanDecoder::<_, char>::with_table(&compressed[..], None).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        let mut encoder = HuffmanEncoder::<_, char>::with_table(Vec::new(), None);
+        encoder.write_all(&"é".as_bytes()[..1]).unwrap();
+        assert!(encoder.finish().is_err());
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        let mut encoder = HuffmanEncoder::new(Vec::new());
+        encoder.write_all(b"some content to compress").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        let err = HuffmanDecoder::new(&compressed[..compressed.len() - 1]).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,19 +1,155 @@
-use std::error::Error;
 use std::path::Path;
 
 
-use crate::huffman::{encode_bitvec_huffman, decode_bitvec_huffman};
+use crate::analysis::{analyze_files, ReportFormat};
+use crate::bwt::DEFAULT_BLOCK_SIZE;
+use crate::archive::{compress_files, decode_archive, decompress_archives, read_archive};
+use crate::export::{export_files, ExportFormat, TreeShape};
+use crate::huffman::{DEFAULT_MAX_CODE_LENGTH, TableMode};
+use crate::lz77::{DEFAULT_MAX_MATCH, DEFAULT_WINDOW_SIZE};
+use crate::lzw::DEFAULT_LZW_CODE_BITS;
 
+pub use crate::archive::{ArchiveBuilder, NamedFile};
+pub use crate::error::Error;
 
+
+pub mod adaptive;
+pub mod analysis;
+pub mod archive;
+pub mod bitstream;
+pub mod bwt;
+pub mod context;
+pub mod deflate;
+pub mod error;
+pub mod export;
 pub mod huffman; 
+pub mod lz77;
+pub mod lzw;
+pub mod presets;
+pub mod rle;
+pub mod words;
 
 pub enum Action {
     Compress,
     Decompress,
+    /// Prints the Huffman tree of the files instead of compressing them
+    Export { format: ExportFormat, shape: TreeShape },
+    /// Prints what compressing the files would give, without writing anything
+    Analyze(ReportFormat),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EncodingMethod {
     Huffman,
+    HuffmanBytes,
+    AdaptiveHuffman,
+    Words,
+    ContextHuffman,
+    Lz77,
+    Deflate,
+    Lzw,
+    Rle,
+    RleHuffman,
+    Bwt,
+}
+
+impl EncodingMethod {
+    /// Returns the id identifying the method in the archive header.
+    pub fn id(&self) -> u8 {
+        match self {
+            EncodingMethod::Huffman => 0,
+            EncodingMethod::HuffmanBytes => 1,
+            EncodingMethod::AdaptiveHuffman => 2,
+            EncodingMethod::Words => 3,
+            EncodingMethod::ContextHuffman => 4,
+            EncodingMethod::Lz77 => 5,
+            EncodingMethod::Deflate => 6,
+            EncodingMethod::Lzw => 7,
+            EncodingMethod::Rle => 8,
+            EncodingMethod::RleHuffman => 9,
+            EncodingMethod::Bwt => 10,
+        }
+    }
+
+    /// Returns the method matching the id read in an archive header.
+    pub fn from_id(id: u8) -> Option<EncodingMethod> {
+        match id {
+            0 => Some(EncodingMethod::Huffman),
+            1 => Some(EncodingMethod::HuffmanBytes),
+            2 => Some(EncodingMethod::AdaptiveHuffman),
+            3 => Some(EncodingMethod::Words),
+            4 => Some(EncodingMethod::ContextHuffman),
+            5 => Some(EncodingMethod::Lz77),
+            6 => Some(EncodingMethod::Deflate),
+            7 => Some(EncodingMethod::Lzw),
+            8 => Some(EncodingMethod::Rle),
+            9 => Some(EncodingMethod::RleHuffman),
+            10 => Some(EncodingMethod::Bwt),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding method matching the name given with the `-m` option.
+    pub fn from_name(name: &str) -> Option<EncodingMethod> {
+        match name {
+            "huffman" => Some(EncodingMethod::Huffman),
+            "huffman-bytes" => Some(EncodingMethod::HuffmanBytes),
+            "adaptive" => Some(EncodingMethod::AdaptiveHuffman),
+            "words" => Some(EncodingMethod::Words),
+            "context" => Some(EncodingMethod::ContextHuffman),
+            "lz77" => Some(EncodingMethod::Lz77),
+            "deflate" => Some(EncodingMethod::Deflate),
+            "lzw" => Some(EncodingMethod::Lzw),
+            "rle" => Some(EncodingMethod::Rle),
+            "rle-huffman" => Some(EncodingMethod::RleHuffman),
+            "bwt" => Some(EncodingMethod::Bwt),
+            _ => None,
+        }
+    }
+}
+
+
+/// How archives are encoded, whether they are made from files or in memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub encoding: EncodingMethod,
+    /// Maximum length of the Huffman codes in bits, `None` for no limit
+    pub max_code_length: Option<usize>,
+    /// Where the Huffman code tables are stored: shared by all files, one per file, or whichever is smaller
+    pub table_mode: TableMode,
+    /// Number of bytes back a match can start at, and maximum length of a match, for the dictionary methods
+    pub window_size: usize,
+    pub max_match: usize,
+    /// Number of bytes sorted together by the block-sorting method
+    pub block_size: usize,
+    /// Width in bits the LZW codes grow to before the dictionary starts over
+    pub max_lzw_code_bits: usize,
+}
+
+impl Options {
+    /// Default options with the given encoding method. The words method has no code length limit by default: its
+    /// dictionary can hold more tokens than 15-bit codes can number.
+    pub fn with_encoding(encoding: EncodingMethod) -> Options {
+        let max_code_length = match encoding {
+            EncodingMethod::Words => None,
+            _ => Some(DEFAULT_MAX_CODE_LENGTH),
+        };
+        Options { encoding, max_code_length, ..Options::default() }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            encoding: EncodingMethod::Huffman,
+            max_code_length: Some(DEFAULT_MAX_CODE_LENGTH),
+            table_mode: TableMode::Auto,
+            window_size: DEFAULT_WINDOW_SIZE,
+            max_match: DEFAULT_MAX_MATCH,
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_lzw_code_bits: DEFAULT_LZW_CODE_BITS,
+        }
+    }
 }
 
 
@@ -21,8 +157,7 @@ pub struct Config {
     pub archive_name: String,
     pub files: Vec<String>,
     pub action: Action,
-    pub encoding: EncodingMethod,
-   
+    pub options: Options,
 }
 
 
@@ -31,9 +166,25 @@ impl Config {
     /// Parses the parameters given inline. 
     /// If the action is "compress", the config struct will have an archive name and vector of files to compress and 
     /// If ... "decompress", the config will look for archive names, the "archive name" parameter won't be used. 
-    pub fn build(mut args: Vec<String>) -> Result<Config, Box<dyn Error>> {
+    /// The encoding method can be chosen with `-m <method>` ("huffman" by default, "huffman-bytes" for binary files,
+    /// "adaptive", "words" for natural-language text, "context" for order-1 context coding, "lz77" for repetitive content,
+    /// "deflate" for LZ77 followed by Huffman coding, "lzw", "rle" for long runs of bytes, "rle-huffman" for run-length
+    /// encoding followed by Huffman coding, "bwt" for block sorting as in bzip2).
+    /// It is only used to compress, archives store the method they were written with.
+    /// The maximum length of the Huffman codes can be chosen with `-l <bits>` (15 by default, no limit with "words",
+    /// 0 for no limit).
+    /// The Huffman tables can be shared by all files or built for each file with `-t <shared|per-file|auto>` ("auto" by default),
+    /// or be a built-in table with `-t <english|rust|json|uniform>`.
+    /// The window of the dictionary methods is set with `--window <bytes>` (32768 by default) and their maximum match
+    /// length with `--max-match <bytes>` (258 by default).
+    /// The blocks of the block-sorting method are set with `--block-size <bytes>` (900000 by default).
+    /// The width the LZW codes grow to is set with `--lzw-bits <bits>` (16 by default, from 9 to 24).
+    /// `-e <dot|json>` prints the Huffman tree of the files (or of the content of archives) instead, the tree built by
+    /// the algorithm or the tree of the canonical codes being chosen with `--tree <huffman|canonical>` ("huffman" by default).
+    /// `-a <text|json>` prints the analysis of each file (entropy, code length, predicted archive size...) instead.
+    pub fn build(mut args: Vec<String>) -> Result<Config, Error> {
         if args.len() <= 2 {
-            return Err("Not enough arguments.".into());
+            return Err(Error::InvalidArgument("Not enough arguments.".into()));
         }
     
         args.remove(0); // Remove first element, program name
@@ -42,16 +193,68 @@ impl Config {
         let mut found_action = false;
         let mut found_archive_name = false;
         let mut files = Vec::new();
-        let encoding = EncodingMethod::Huffman;
+
+        // Extracts the options and their values from the arguments
+        let mut encoding = EncodingMethod::Huffman;
+        if let Some(name) = take_option(&mut args, "-m")? {
+            encoding = EncodingMethod::from_name(&name).ok_or(Error::InvalidArgument(format!("Unknown encoding method: {}", name)))?;
+        }
+        let mut options = Options::with_encoding(encoding);
+
+        if let Some(value) = take_option(&mut args, "-l")? {
+            let bits: usize = value.parse().map_err(|_| Error::InvalidArgument(format!("Invalid maximum code length: {}", value)))?;
+            options.max_code_length = match bits {
+                0 => None,
+                1..=64 => Some(bits),
+                _ => return Err(Error::InvalidArgument("The maximum code length must be between 1 and 64 bits (0 for no limit).".into())),
+            };
+        }
+
+        if let Some(name) = take_option(&mut args, "-t")? {
+            options.table_mode = TableMode::from_name(&name).ok_or(Error::InvalidArgument(format!("Unknown table mode: {}", name)))?;
+        }
+
+        if let Some(value) = take_option(&mut args, "--window")? {
+            options.window_size = value.parse().map_err(|_| Error::InvalidArgument(format!("Invalid window size: {}", value)))?;
+        }
+
+        if let Some(value) = take_option(&mut args, "--max-match")? {
+            options.max_match = value.parse().map_err(|_| Error::InvalidArgument(format!("Invalid maximum match length: {}", value)))?;
+        }
+
+        if let Some(value) = take_option(&mut args, "--block-size")? {
+            options.block_size = value.parse().map_err(|_| Error::InvalidArgument(format!("Invalid block size: {}", value)))?;
+        }
+
+        if let Some(value) = take_option(&mut args, "--lzw-bits")? {
+            options.max_lzw_code_bits = value.parse().map_err(|_| Error::InvalidArgument(format!("Invalid LZW code width: {}", value)))?;
+        }
+
+        let mut shape = TreeShape::Huffman;
+        if let Some(name) = take_option(&mut args, "--tree")? {
+            shape = TreeShape::from_name(&name).ok_or(Error::InvalidArgument(format!("Unknown tree: {}", name)))?;
+        }
+
+        if let Some(name) = take_option(&mut args, "-e")? {
+            let format = ExportFormat::from_name(&name).ok_or(Error::InvalidArgument(format!("Unknown export format: {}", name)))?;
+            action = Action::Export { format, shape };
+            found_action = true;
+        }
+
+        if let Some(name) = take_option(&mut args, "-a")? {
+            let format = ReportFormat::from_name(&name).ok_or(Error::InvalidArgument(format!("Unknown report format: {}", name)))?;
+            action = Action::Analyze(format);
+            found_action = true;
+        }
 
         
         // Parses the arguments to find the action to proceed
         for param in &args {
-            if param == "-d" && found_action == false {
+            if param == "-d" && !found_action {
                 action = Action::Decompress;
                 found_action = true;
                 break;
-            } else if param == "-c" && found_action == false {
+            } else if param == "-c" && !found_action {
                 action = Action::Compress;
                 found_action = true;
                 break;
@@ -60,8 +263,8 @@ impl Config {
 
 
         if !found_action {
-            return Err("User didn't provide an action for the program.\n 
-                        -c to compress, -d to decompress.".into());
+            return Err(Error::InvalidArgument("User didn't provide an action for the program.\n 
+                        -c to compress, -d to decompress, -e <dot|json> to export the Huffman tree, -a <text|json> to analyze files.".into()));
         }
 
         match action {
@@ -71,7 +274,7 @@ impl Config {
                         archive_name = param;
                         found_archive_name = true;
                     } else if param.contains(".zip") && found_archive_name { 
-                        return Err("Two archive names were given. Cannot compress an archive (yet).".into());
+                        return Err(Error::InvalidArgument("Two archive names were given. Cannot compress an archive (yet).".into()));
                     } else if param == "-d" || param == "-c"{continue;}
                     else {
                         files.push(param.clone());
@@ -82,7 +285,10 @@ impl Config {
                 for param in &args {
                     if param.contains(".zip") {files.push(param.clone());}
                 }
-            }
+            },
+            Action::Export { .. } | Action::Analyze(_) => {
+                files.extend(args.iter().filter(|param| *param != "-c" && *param != "-d").cloned());
+            },
         }
 
         // If no archive name is provided, if trying to decompress, throws error,
@@ -91,14 +297,14 @@ impl Config {
         //     return Err("Error: user need to provide an archive name.".into());          
         // }
         
-        if files.len() <= 0 {
-            return Err("User must provide files to compress or decompress.".into());
+        if files.is_empty() {
+            return Err(Error::InvalidArgument("User must provide files to compress or decompress.".into()));
         }
 
         // Checks if provided fils in arguments exist
         for file in &files {
             if !Path::new(&file).exists() {
-                return Err(format!("File {} doesn't exist.", file).into());
+                return Err(Error::InvalidArgument(format!("File {} doesn't exist.", file)));
             }            
         }
         
@@ -106,7 +312,7 @@ impl Config {
             archive_name: archive_name.into(),
             files,
             action,
-            encoding,
+            options,
         })
     }
 }
@@ -114,16 +320,58 @@ impl Config {
 
 
 
+/// Removes the option `flag` and its value from the arguments, and returns the value if the option was given.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, Error> {
+    let Some(pos) = args.iter().position(|param| param == flag) else {
+        return Ok(None);
+    };
+    if pos + 1 >= args.len() {
+        return Err(Error::InvalidArgument(format!("Option {} needs a value.", flag)));
+    }
+
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+
+/// Compresses a buffer in memory and returns the bytes of an archive holding it as a single unnamed file.
+pub fn compress(content: &[u8], method: EncodingMethod) -> Result<Vec<u8>, Error> {
+    let mut builder = ArchiveBuilder::new(Options::with_encoding(method));
+    builder.add_file("", content);
+    builder.build()
+}
+
+
+/// Decompresses an archive made by `compress`, or any archive holding a single file, and returns the content of the file.
+pub fn decompress(archive: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut contents = decode_archive(&read_archive(archive)?)?;
+    if contents.len() != 1 {
+        return Err(Error::InvalidArgument(format!("The archive holds {} files, use `decompress_files` to get all of them.", contents.len())));
+    }
+    Ok(contents.remove(0))
+}
+
+
+/// Decompresses an archive in memory and returns the name and content of each of its files.
+pub fn decompress_files(archive: &[u8]) -> Result<Vec<NamedFile>, Error> {
+    let archive = read_archive(archive)?;
+    let contents = decode_archive(&archive)?;
+    Ok(archive.members.into_iter().map(|member| member.name).zip(contents).collect())
+}
+
+
 /// Main function that runs the logic of the program, according to the `Config` parameter.
-pub fn run (config: Config) -> Result<(), Box<dyn Error>> {
+pub fn run (config: Config) -> Result<(), Error> {
     match config.action {
-        Action::Compress => match config.encoding {
-            EncodingMethod::Huffman => encode_bitvec_huffman(&config)?,
-        },
+        Action::Compress => compress_files(&config)?,
 
-        Action::Decompress => match config.encoding {
-            EncodingMethod::Huffman => decode_bitvec_huffman(&config)?,
-        },
+        // The encoding method is read from the header of each archive
+        Action::Decompress => decompress_archives(&config)?,
+
+        Action::Export { format, shape } => export_files(&config, shape, format)?,
+
+        Action::Analyze(format) => analyze_files(&config, format)?,
     }
     
 
@@ -134,3 +382,61 @@ pub fn run (config: Config) -> Result<(), Box<dyn Error>> {
 
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [EncodingMethod; 11] = [
+        EncodingMethod::Huffman, EncodingMethod::HuffmanBytes, EncodingMethod::AdaptiveHuffman, EncodingMethod::Words, EncodingMethod::ContextHuffman,
+        EncodingMethod::Lz77, EncodingMethod::Deflate, EncodingMethod::Lzw, EncodingMethod::Rle, EncodingMethod::RleHuffman,
+        EncodingMethod::Bwt,
+    ];
+
+    #[test]
+    fn compress_round_trips_with_every_method() {
+        for content in [&b"abracadabra, abracadabra"[..], b"", b"aaaa"] {
+            for method in METHODS {
+                assert_eq!(decompress(&compress(content, method).unwrap()).unwrap(), content, "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn compress_rejects_binary_content_as_text() {
+        assert!(compress(&[0xFF, 0xFE, 0x00], EncodingMethod::Huffman).is_err());
+        assert!(compress(&[0xFF, 0xFE, 0x00], EncodingMethod::HuffmanBytes).is_ok());
+    }
+
+    #[test]
+    fn errors_tell_their_kind() {
+        assert!(matches!(compress(&[0xFF], EncodingMethod::Huffman), Err(Error::InvalidArgument(_))));
+        assert!(matches!(decompress(b"not an archive"), Err(Error::CorruptArchive { offset: Some(0), .. })));
+
+        let mut archive = compress(b"some text", EncodingMethod::Huffman).unwrap();
+        archive[4] += 1;
+        assert!(matches!(decompress(&archive), Err(Error::UnsupportedVersion { .. })));
+
+        let archive = compress(b"some text", EncodingMethod::Huffman).unwrap();
+        assert!(matches!(decompress(&archive[..archive.len() - 1]), Err(Error::CorruptArchive { .. })));
+    }
+
+    #[test]
+    fn archive_builder_round_trips_named_files() {
+        let files = vec![
+            ("a.txt".to_string(), b"first file".to_vec()),
+            ("b.txt".to_string(), b"second file, a bit longer than the first one".to_vec()),
+            ("".to_string(), Vec::new()),
+        ];
+
+        for method in METHODS {
+            let mut builder = ArchiveBuilder::new(Options::with_encoding(method));
+            for (name, content) in &files {
+                builder.add_file(name.as_str(), content.as_slice());
+            }
+            let archive = builder.build().unwrap();
+
+            assert_eq!(decompress_files(&archive).unwrap(), files, "{:?}", method);
+            assert!(decompress(&archive).is_err());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{EncodingMethod, Options};
+use crate::archive::{write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::error::Error;
+
+
+// ---------- Parameters -----------
+
+/// Shortest match worth a token: shorter repetitions are written as literals.
+pub const MIN_MATCH: usize = 3;
+
+/// Default number of bytes back a match can start at.
+pub const DEFAULT_WINDOW_SIZE: usize = 32 * 1024;
+
+/// Largest window allowed, so the decoder never has to keep more than that in memory.
+pub const MAX_WINDOW_SIZE: usize = 1 << 24;
+
+/// Default maximum length of a match.
+pub const DEFAULT_MAX_MATCH: usize = 258;
+
+/// Number of bits of the hash of the `MIN_MATCH` bytes starting a match, giving the number of hash chains.
+const HASH_BITS: usize = 15;
+
+/// Number of earlier positions tried for each match, from the closest one. More finds longer matches, but takes longer.
+const MAX_CHAIN: usize = 128;
+
+
+/// Token of the compressed stream: a byte copied as is, or a copy of `length` bytes starting `offset` bytes back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    Literal(u8),
+    Match { offset: usize, length: usize },
+}
+
+
+// #################################
+// ------- Match finder ------------
+// #################################
+
+/// Finds the longest earlier occurrence of the bytes at each position, through hash chains: `head` gives the last
+/// position whose first `MIN_MATCH` bytes have a given hash, and `previous` links each position to the previous one
+/// with the same hash. `previous` is a ring of the size of the window (of at least one slot, for a window of 0 bytes
+/// which finds no match): the links of the positions which left the window are overwritten, but they are never followed.
+struct MatchFinder<'a> {
+    content: &'a [u8],
+    window_size: usize,
+    max_match: usize,
+    head: Vec<Option<usize>>,
+    previous: Vec<Option<usize>>,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(content: &'a [u8], window_size: usize, max_match: usize) -> MatchFinder<'a> {
+        MatchFinder {
+            content,
+            window_size,
+            max_match,
+            head: vec![None; 1 << HASH_BITS],
+            previous: vec![None; window_size.clamp(1, content.len().max(1))],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.content[pos..pos + MIN_MATCH];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Adds the position to the chain of its hash. Positions too close to the end to start a match are skipped.
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.content.len() {
+            return;
+        }
+        let hash = self.hash(pos);
+        let slot = pos % self.previous.len();
+        self.previous[slot] = self.head[hash];
+        self.head[hash] = Some(pos);
+    }
+
+    /// Returns the (offset, length) of the longest match for the bytes at `pos` within the window, the closest one
+    /// on ties. `pos` must not be inserted yet.
+    fn longest_match(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.content.len() {
+            return None;
+        }
+        let max_len = self.max_match.min(self.content.len() - pos);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(pos)];
+
+        for _ in 0..MAX_CHAIN {
+            let Some(start) = candidate.filter(|&start| pos - start <= self.window_size) else { break };
+            let length = self.content[start..].iter().zip(&self.content[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+            if length >= MIN_MATCH && best.is_none_or(|(_, best_len)| length > best_len) {
+                best = Some((pos - start, length));
+                if length == max_len {
+                    break;
+                }
+            }
+            candidate = self.previous[start % self.previous.len()];
+        }
+
+        best
+    }
+}
+
+
+/// Cuts `content` into tokens, greedily taking the longest match at each position when it is shorter to write than
+/// its bytes as literals.
+pub fn tokenize(content: &[u8], window_size: usize, max_match: usize) -> Vec<Token> {
+    tokenize_with(content, window_size, max_match, |offset, length| length > match_size(offset, length))
+}
+
+
+/// Cuts `content` into tokens, greedily taking the longest match at each position when `keep(offset, length)` says it
+/// is worth it, the codecs writing matches in different ways.
+pub fn tokenize_with(content: &[u8], window_size: usize, max_match: usize, keep: impl Fn(usize, usize) -> bool) -> Vec<Token> {
+    let mut finder = MatchFinder::new(content, window_size, max_match);
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < content.len() {
+        match finder.longest_match(pos) {
+            Some((offset, length)) if keep(offset, length) => {
+                tokens.push(Token::Match { offset, length });
+                for inserted in pos..pos + length {
+                    finder.insert(inserted);
+                }
+                pos += length;
+            },
+            _ => {
+                tokens.push(Token::Literal(content[pos]));
+                finder.insert(pos);
+                pos += 1;
+            },
+        }
+    }
+
+    tokens
+}
+
+
+/// Size in bytes of a match token, its bit in the flag byte excluded.
+fn match_size(offset: usize, length: usize) -> usize {
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, offset as u64);
+    write_varint(&mut bytes, (length - MIN_MATCH) as u64);
+    bytes.len()
+}
+
+
+// #################################
+// ------- Token stream ------------
+// #################################
+
+/// Returns the token stream: the tokens go by groups of 8, each group starting with a flag byte whose bits (least
+/// significant first) tell if each token is a literal (0) or a match (1). A literal is its byte, a match is `offset`
+/// (varint), then `length - MIN_MATCH` (varint).
+pub fn write_tokens(tokens: &[Token]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for group in tokens.chunks(8) {
+        let flags_pos = bytes.len();
+        bytes.push(0);
+
+        for (index, token) in group.iter().enumerate() {
+            match *token {
+                Token::Literal(byte) => bytes.push(byte),
+                Token::Match { offset, length } => {
+                    bytes[flags_pos] |= 1 << index;
+                    write_varint(&mut bytes, offset as u64);
+                    write_varint(&mut bytes, (length - MIN_MATCH) as u64);
+                },
+            }
+        }
+    }
+    bytes
+}
+
+
+/// Decodes a token stream written by `write_tokens` until `original_len` bytes are produced. Matches can't reach
+/// further back than `window_size` bytes, nor before the start of the content.
+pub fn read_tokens(payload: &[u8], original_len: u64, window_size: usize) -> Result<Vec<u8>, Error> {
+    let mut reader = ByteReader::new(payload);
+    let mut content: Vec<u8> = Vec::new();
+    let mut flags = 0;
+
+    for index in 0.. {
+        if content.len() as u64 >= original_len {
+            break;
+        }
+        if index % 8 == 0 {
+            flags = reader.read_u8()?;
+        }
+
+        if flags & (1 << (index % 8)) == 0 {
+            content.push(reader.read_u8()?);
+            continue;
+        }
+
+        let start = reader.position();
+        let offset = reader.read_varint()? as usize;
+        let length = (reader.read_varint()? as usize).saturating_add(MIN_MATCH);
+        if offset == 0 || offset > content.len() || offset > window_size {
+            return Err(Error::corrupt_at(start, format!("match {} bytes back, after {} bytes", offset, content.len())));
+        }
+        if content.len() as u64 + length as u64 > original_len {
+            return Err(Error::corrupt_at(start, "match goes past the end of the file"));
+        }
+
+        // Byte by byte, as a match may overlap the bytes it produces (a run of one byte is a match 1 byte back)
+        let from = content.len() - offset;
+        for i in 0..length {
+            content.push(content[from + i]);
+        }
+    }
+
+    if reader.position() != payload.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the end of the file"));
+    }
+    Ok(content)
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Checks the window size and maximum match length of `options`.
+pub fn check_match_options(options: &Options) -> Result<(), Error> {
+    if !(1..=MAX_WINDOW_SIZE).contains(&options.window_size) {
+        return Err(Error::InvalidArgument(format!("The window size must be between 1 and {} bytes.", MAX_WINDOW_SIZE)));
+    }
+    if options.max_match < MIN_MATCH {
+        return Err(Error::InvalidArgument(format!("The maximum match length must be at least {} bytes.", MIN_MATCH)));
+    }
+    Ok(())
+}
+
+
+/// Returns the archive of the files `files`, using LZ77 with the window size and maximum match length of `options`.
+///
+/// The method header holds the `window size` (varint), which the decoder checks the matches against. Each file is
+/// coded on its own, matches never going back into another file, and its payload is its token stream
+/// (see `write_tokens`).
+pub fn encode_lz77(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    check_match_options(options)?;
+
+    let mut method_header = Vec::new();
+    write_varint(&mut method_header, options.window_size as u64);
+
+    let members = files.iter().map(|(name, content)| Member {
+        name: name.clone(),
+        original_len: content.len() as u64,
+        payload: write_tokens(&tokenize(content, options.window_size, options.max_match)),
+    }).collect();
+
+    Ok(Archive { method: EncodingMethod::Lz77, method_header, members })
+}
+
+
+/// Decodes archives written by `encode_lz77`, returning the content of each of their files.
+pub fn decode_lz77(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let window_size = reader.read_varint()? as usize;
+    if window_size > MAX_WINDOW_SIZE {
+        return Err(Error::corrupt_at(0, format!("window of {} bytes", window_size)));
+    }
+
+    archive.members.iter()
+        .map(|member| read_tokens(&member.payload, member.original_len, window_size))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repetitions_become_matches() {
+        let tokens = tokenize(b"abcabcabcabcx", DEFAULT_WINDOW_SIZE, DEFAULT_MAX_MATCH);
+        assert_eq!(tokens, vec![
+            Token::Literal(b'a'), Token::Literal(b'b'), Token::Literal(b'c'),
+            Token::Match { offset: 3, length: 9 },
+            Token::Literal(b'x'),
+        ]);
+
+        // Matches are cut at the maximum length, and never reach further back than the window
+        let content = b"0123456789".repeat(20);
+        for token in tokenize(&content, 10, 16) {
+            if let Token::Match { offset, length } = token {
+                assert!(offset <= 10 && length <= 16);
+            }
+        }
+        assert_eq!(read_tokens(&write_tokens(&tokenize(&content, 10, 16)), 200, 10).unwrap(), content);
+    }
+
+    #[test]
+    fn matches_out_of_the_window_are_rejected() {
+        let payload = write_tokens(&[Token::Literal(b'a'), Token::Literal(b'b'), Token::Match { offset: 2, length: 4 }]);
+        assert_eq!(read_tokens(&payload, 6, 2).unwrap(), b"ababab");
+        assert!(read_tokens(&payload, 6, 1).is_err());
+        assert!(read_tokens(&payload, 5, 2).is_err());
+    }
+
+    #[test]
+    fn tiny_windows_tokenize() {
+        let content = b"aaaaaabab";
+        let tokens = tokenize_with(content, 0, DEFAULT_MAX_MATCH, |_, _| true);
+        assert_eq!(tokens, content.iter().map(|&byte| Token::Literal(byte)).collect::<Vec<_>>());
+
+        let tokens = tokenize_with(content, 1, DEFAULT_MAX_MATCH, |_, _| true);
+        assert_eq!(tokens[..2], [Token::Literal(b'a'), Token::Match { offset: 1, length: 5 }]);
+        assert!(tokens[2..].iter().all(|token| matches!(token, Token::Literal(_))));
+        assert!(tokenize_with(b"", 0, DEFAULT_MAX_MATCH, |_, _| true).is_empty());
+    }
+}
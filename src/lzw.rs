@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use crate::{EncodingMethod, Options};
+use crate::archive::{write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::bitstream::{BitReader, BitWriter};
+use crate::error::Error;
+use crate::huffman::{read_char, HuffmanSymbol};
+
+
+// ---------- Parameters -----------
+
+/// Width of the codes after each clear code, unless the alphabet needs more.
+const MIN_CODE_BITS: usize = 9;
+
+/// Width the codes grow to by default, as with `compress`.
+pub const DEFAULT_LZW_CODE_BITS: usize = 16;
+
+/// Widest codes allowed: beyond that, the dictionary takes too much memory for what it saves.
+pub const MAX_LZW_CODE_BITS: usize = 24;
+
+/// First byte of the method header, telling which symbols the codes are built on.
+const BYTE_SYMBOLS: u8 = 0;
+const CHAR_SYMBOLS: u8 = 1;
+
+
+/// Appends the alphabet `chars` to `bytes`: `character count` (varint), then the characters in UTF-8, in increasing order.
+fn write_char_list(bytes: &mut Vec<u8>, chars: &[char]) {
+    write_varint(bytes, chars.len() as u64);
+    for ch in chars {
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+    }
+}
+
+
+/// Reads the alphabet written by `write_char_list`.
+fn read_char_list(reader: &mut ByteReader) -> Result<Vec<char>, Error> {
+    let count = reader.read_varint()?;
+    let mut chars: Vec<char> = Vec::new();
+    for _ in 0..count {
+        let start = reader.position();
+        let ch = read_char(reader)?;
+        if chars.last().is_some_and(|&last| last >= ch) {
+            return Err(Error::corrupt_at(start, "alphabet characters are not in increasing order"));
+        }
+        chars.push(ch);
+    }
+    Ok(chars)
+}
+
+
+/// Number of bits needed to write every code below `next_code`, the next code the encoder will give.
+fn code_width(next_code: usize) -> usize {
+    ((usize::BITS - (next_code - 1).leading_zeros()) as usize).max(MIN_CODE_BITS)
+}
+
+
+// #################################
+// ------- Encoder -----------------
+// #################################
+
+/// Returns the LZW codes of `symbols`, given as indices in an alphabet of `alphabet_size` symbols, in a bitstream
+/// (see `BitWriter::finish`).
+///
+/// The dictionary starts with one code per symbol, then the clear code (`alphabet_size`), and each code written adds the
+/// string it stands for followed by the next symbol. Codes are written with the fewest bits that can hold every code
+/// given so far, from 9 bits up to `max_code_bits`. When the dictionary is full, a clear code is written and the
+/// dictionary starts over.
+pub fn encode_lzw(symbols: &[u32], alphabet_size: usize, max_code_bits: usize) -> Vec<u8> {
+    let clear_code = alphabet_size as u32;
+    let first_code = alphabet_size + 1;
+    let max_codes = 1 << max_code_bits;
+
+    let mut encoded = BitWriter::new();
+    let mut dictionary: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut next_code = first_code;
+    let mut current: Option<u32> = None;
+
+    for &symbol in symbols {
+        let Some(prefix) = current else {
+            current = Some(symbol);
+            continue;
+        };
+        if let Some(&code) = dictionary.get(&(prefix, symbol)) {
+            current = Some(code);
+            continue;
+        }
+
+        encoded.write_int(prefix as u64, code_width(next_code));
+        if next_code < max_codes {
+            dictionary.insert((prefix, symbol), next_code as u32);
+            next_code += 1;
+        } else {
+            encoded.write_int(clear_code as u64, code_width(next_code));
+            dictionary.clear();
+            next_code = first_code;
+        }
+        current = Some(symbol);
+    }
+
+    if let Some(prefix) = current {
+        encoded.write_int(prefix as u64, code_width(next_code));
+    }
+    encoded.finish()
+}
+
+
+// #################################
+// ------- Decoder -----------------
+// #################################
+
+/// String of the dictionary: the code of the string it extends (`NO_PREFIX` for single symbols) and its last symbol.
+/// Strings are written out by going back through their prefixes, so an entry only takes 8 bytes.
+#[derive(Clone, Copy)]
+struct Entry {
+    prefix: u32,
+    symbol: u32,
+}
+
+const NO_PREFIX: u32 = u32::MAX;
+
+
+/// Replaces the content of `string` by the symbols of the string of `code`.
+fn write_string(dictionary: &[Entry], code: u32, string: &mut Vec<u32>) {
+    string.clear();
+    let mut current = code;
+    loop {
+        let entry = dictionary[current as usize];
+        string.push(entry.symbol);
+        // A prefix always comes before the entries extending it, so this ends
+        if entry.prefix == NO_PREFIX {
+            break;
+        }
+        current = entry.prefix;
+    }
+    string.reverse();
+}
+
+
+/// Decodes the LZW codes of `payload`, written by `encode_lzw`, until the symbols add up to `original_len` bytes,
+/// `symbol_len` giving the number of bytes of each symbol of the alphabet.
+pub fn decode_lzw(payload: &[u8], alphabet_size: usize, max_code_bits: usize, original_len: u64, symbol_len: impl Fn(u32) -> u64) -> Result<Vec<u32>, Error> {
+    let first_code = alphabet_size + 1;
+    let max_codes = 1 << max_code_bits;
+
+    let mut reader = BitReader::new(payload)?;
+    let mut dictionary: Vec<Entry> = (0..first_code as u32).map(|symbol| Entry { prefix: NO_PREFIX, symbol }).collect();
+    // Mirrors the `next_code` of the encoder, which is one entry ahead of the dictionary of the decoder
+    let mut next_code = first_code;
+    let mut previous: Option<u32> = None;
+    let mut string: Vec<u32> = Vec::new();
+    let mut symbols: Vec<u32> = Vec::new();
+    let mut decoded_len = 0;
+
+    while decoded_len < original_len {
+        let code = reader.read_int(code_width(next_code))? as u32;
+        if code as usize == alphabet_size {
+            dictionary.truncate(first_code);
+            next_code = first_code;
+            previous = None;
+            continue;
+        }
+
+        // A code can stand for the entry the decoder is about to add, which is the previous string plus its first symbol
+        match previous {
+            _ if (code as usize) < dictionary.len() => write_string(&dictionary, code, &mut string),
+            Some(previous) if code as usize == dictionary.len() => {
+                write_string(&dictionary, previous, &mut string);
+                string.push(string[0]);
+            },
+            _ => return Err(Error::corrupt(format!("LZW code {} not in the dictionary ({} entries)", code, dictionary.len()))),
+        }
+        if let Some(previous) = previous {
+            dictionary.push(Entry { prefix: previous, symbol: string[0] });
+        }
+
+        decoded_len += string.iter().map(|&symbol| symbol_len(symbol)).sum::<u64>();
+        symbols.extend_from_slice(&string);
+
+        previous = Some(code);
+        if next_code < max_codes {
+            next_code += 1;
+        }
+    }
+
+    if decoded_len != original_len {
+        return Err(Error::corrupt("decoded file is longer than its original length"));
+    }
+    if reader.remaining() != 0 {
+        return Err(Error::corrupt(format!("{} bits left after the end of the file", reader.remaining())));
+    }
+    Ok(symbols)
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Returns the archive of `files` with LZW codes on the symbols `S`, whose alphabet is `alphabet` (sorted).
+fn build_lzw_archive<S: HuffmanSymbol>(files: &[NamedFile], alphabet: &[S], max_code_bits: usize, method_header: Vec<u8>) -> Result<Archive, Error> {
+    if alphabet.len() + 1 >= 1 << max_code_bits {
+        return Err(Error::LimitExceeded(format!("{} symbols don't leave room for LZW codes of {} bits.", alphabet.len(), max_code_bits)));
+    }
+    let index: HashMap<S, u32> = alphabet.iter().enumerate().map(|(index, &symbol)| (symbol, index as u32)).collect();
+
+    let mut members = Vec::new();
+    for (name, content) in files {
+        let symbols: Vec<u32> = S::split(content)?.into_iter().map(|symbol| index[&symbol]).collect();
+        members.push(Member {
+            name: name.clone(),
+            original_len: content.len() as u64,
+            payload: encode_lzw(&symbols, alphabet.len(), max_code_bits),
+        });
+    }
+
+    Ok(Archive { method: EncodingMethod::Lzw, method_header, members })
+}
+
+
+/// Returns the archive of the files `files` using LZW, with codes of at most `options.max_lzw_code_bits` bits.
+///
+/// The codes are built on bytes, or on the characters of the files when they are all UTF-8 text and that gives a smaller
+/// archive. The method header holds the `symbols` (u8, 0 for bytes, 1 for characters) and the `maximum code width`
+/// (u8), then for characters, the characters of the files (see `write_char_list`). Each file is coded with a fresh
+/// dictionary, and its payload is the bitstream of its codes (see `encode_lzw`).
+pub fn encode_lzw_archive(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    let max_code_bits = options.max_lzw_code_bits;
+    if !(MIN_CODE_BITS..=MAX_LZW_CODE_BITS).contains(&max_code_bits) {
+        return Err(Error::InvalidArgument(format!("LZW codes must be between {} and {} bits long.", MIN_CODE_BITS, MAX_LZW_CODE_BITS)));
+    }
+
+    let bytes: Vec<u8> = (0..=255).collect();
+    let byte_archive = build_lzw_archive(files, &bytes, max_code_bits, vec![BYTE_SYMBOLS, max_code_bits as u8])?;
+
+    let mut chars: Vec<char> = Vec::new();
+    for (_, content) in files {
+        let Ok(text) = str::from_utf8(content) else { return Ok(byte_archive) };
+        chars.extend(text.chars());
+    }
+    chars.sort_unstable();
+    chars.dedup();
+
+    let mut method_header = vec![CHAR_SYMBOLS, max_code_bits as u8];
+    write_char_list(&mut method_header, &chars);
+    let Ok(char_archive) = build_lzw_archive(files, &chars, max_code_bits, method_header) else { return Ok(byte_archive) };
+
+    let size = |archive: &Archive| archive.method_header.len() + archive.members.iter().map(|member| member.payload.len()).sum::<usize>();
+    Ok(if size(&char_archive) < size(&byte_archive) { char_archive } else { byte_archive })
+}
+
+
+/// Decodes archives written by `encode_lzw_archive`, returning the content of each of their files.
+pub fn decode_lzw_archive(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let symbols = reader.read_u8()?;
+    let max_code_bits = reader.read_u8()? as usize;
+    if !(MIN_CODE_BITS..=MAX_LZW_CODE_BITS).contains(&max_code_bits) {
+        return Err(Error::corrupt_at(1, format!("LZW codes of {} bits", max_code_bits)));
+    }
+
+    let chars: Vec<char> = match symbols {
+        BYTE_SYMBOLS => Vec::new(),
+        CHAR_SYMBOLS => read_char_list(&mut reader)?,
+        symbols => return Err(Error::corrupt_at(0, format!("unknown LZW symbols {}", symbols))),
+    };
+    if reader.position() != archive.method_header.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the alphabet"));
+    }
+    if symbols == CHAR_SYMBOLS && chars.len() + 1 >= 1 << max_code_bits {
+        return Err(Error::corrupt_at(2, "alphabet too large for the code width"));
+    }
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        contents.push(match symbols {
+            BYTE_SYMBOLS => decode_lzw(&member.payload, 256, max_code_bits, member.original_len, |_| 1)?
+                .into_iter().map(|symbol| symbol as u8).collect(),
+            _ => decode_lzw(&member.payload, chars.len(), max_code_bits, member.original_len, |index| chars[index as usize].len_utf8() as u64)?
+                .into_iter().map(|index| chars[index as usize]).collect::<String>().into_bytes(),
+        });
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip_through_clear_codes() {
+        // Small codes fill the dictionary quickly, which clears it several times
+        let text = b"TOBEORNOTTOBEORTOBEORNOT#".repeat(200);
+        let symbols: Vec<u32> = text.iter().map(|&byte| byte as u32).collect();
+        for max_code_bits in [9, 10, 16] {
+            let payload = encode_lzw(&symbols, 256, max_code_bits);
+            assert_eq!(decode_lzw(&payload, 256, max_code_bits, symbols.len() as u64, |_| 1).unwrap(), symbols);
+        }
+
+        // The third code stands for the string the decoder is about to add
+        let symbols = [0, 0, 0, 0, 0, 0];
+        let payload = encode_lzw(&symbols, 2, 9);
+        assert_eq!(decode_lzw(&payload, 2, 9, 6, |_| 1).unwrap(), symbols);
+    }
+
+    #[test]
+    fn text_uses_characters() {
+        let files = vec![("fr.txt".to_string(), "été à l'hôtel, été à la mer ".repeat(20).into_bytes())];
+        let archive = encode_lzw_archive(&files, &Options::with_encoding(EncodingMethod::Lzw)).unwrap();
+        assert_eq!(archive.method_header[0], CHAR_SYMBOLS);
+        assert_eq!(decode_lzw_archive(&archive).unwrap()[0], files[0].1);
+    }
+
+    #[test]
+    fn code_width_has_its_own_option() {
+        let files = vec![("a.bin".to_string(), b"abcabcabcabd".repeat(50))];
+        let mut options = Options { max_code_length: Some(10), ..Options::with_encoding(EncodingMethod::Lzw) };
+        let archive = encode_lzw_archive(&files, &options).unwrap();
+        assert_eq!(archive.method_header[1] as usize, DEFAULT_LZW_CODE_BITS);
+
+        options.max_lzw_code_bits = MAX_LZW_CODE_BITS;
+        let archive = encode_lzw_archive(&files, &options).unwrap();
+        assert_eq!(decode_lzw_archive(&archive).unwrap()[0], files[0].1);
+
+        for max_lzw_code_bits in [MIN_CODE_BITS - 1, MAX_LZW_CODE_BITS + 1] {
+            options.max_lzw_code_bits = max_lzw_code_bits;
+            assert!(matches!(encode_lzw_archive(&files, &options), Err(Error::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn alphabet_is_a_plain_character_list() {
+        let mut bytes = Vec::new();
+        write_char_list(&mut bytes, &['a', 'é', '日']);
+        assert_eq!(bytes, [&[3][..], "aé日".as_bytes()].concat());
+        assert_eq!(read_char_list(&mut ByteReader::new(&bytes)).unwrap(), vec!['a', 'é', '日']);
+
+        // Characters out of order, or cut in the middle
+        assert!(read_char_list(&mut ByteReader::new(&[2, b'b', b'a'])).is_err());
+        assert!(read_char_list(&mut ByteReader::new(&bytes[..bytes.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn character_archives_decode_from_their_header_layout() {
+        // `symbols`, `maximum code width`, then 2 characters: 'a' and 'é'
+        let method_header = [&[CHAR_SYMBOLS, 9, 2, b'a'][..], "é".as_bytes()].concat();
+        let text = "aééaaéa";
+        let indices: Vec<u32> = text.chars().map(|ch| if ch == 'a' { 0 } else { 1 }).collect();
+        let archive = Archive {
+            method: EncodingMethod::Lzw,
+            method_header,
+            members: vec![Member { name: "a.txt".to_string(), original_len: text.len() as u64, payload: encode_lzw(&indices, 2, 9) }],
+        };
+        assert_eq!(decode_lzw_archive(&archive).unwrap()[0], text.as_bytes());
+    }
+}
@@ -1,7 +1,7 @@
 use std::env;
 use std::process;
 
-use my_compressor::Config;
+use my_compressor::{Config, Error};
 pub use my_compressor::huffman;
 
 
@@ -12,17 +12,29 @@ fn main() {
 
     let config = Config::build(args).unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        process::exit(exit_code(&err));
     });
 
 
     // ----- Run Logic and check errors -----
     if let Err(e) = my_compressor::run(config) {
         eprintln!("Application error: {e}");
-        process::exit(1);
+        process::exit(exit_code(&e));
     }
 
 
 
     process::exit(0);
 }
+
+
+/// Exit code of the program for each kind of error, so scripts can tell them apart.
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::InvalidArgument(_) => 2,
+        Error::Io(_) => 3,
+        Error::CorruptArchive { .. } => 4,
+        Error::UnsupportedVersion { .. } => 5,
+        Error::LimitExceeded(_) => 6,
+    }
+}
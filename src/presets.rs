@@ -0,0 +1,131 @@
+use crate::huffman::HuffmanSymbol;
+
+
+// ---------- Presets -----------
+
+/// Built-in Huffman tables, for files too small to pay for their own table. An archive using one only stores its id.
+///
+/// The tables give a code to every byte value (and to the characters up to U+00FF, for the `huffman` method), the
+/// bytes never seen in the samples they were made from getting the longest codes. They are part of the archive format:
+/// changing them requires a new format version.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    /// English prose
+    English,
+    /// Rust source code
+    Rust,
+    /// Indented JSON
+    Json,
+    /// Every byte gets 8 bits: nothing is compressed, but nothing grows either
+    Uniform,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [Preset::English, Preset::Rust, Preset::Json, Preset::Uniform];
+
+    /// Returns the id identifying the preset in the method header.
+    pub fn id(&self) -> u8 {
+        match self {
+            Preset::English => 0,
+            Preset::Rust => 1,
+            Preset::Json => 2,
+            Preset::Uniform => 3,
+        }
+    }
+
+    /// Returns the preset matching the id read in a method header.
+    pub fn from_id(id: u8) -> Option<Preset> {
+        Preset::ALL.into_iter().find(|preset| preset.id() == id)
+    }
+
+    /// Returns the preset matching the name given with the `-t` option.
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "english" => Some(Preset::English),
+            "rust" => Some(Preset::Rust),
+            "json" => Some(Preset::Json),
+            "uniform" => Some(Preset::Uniform),
+            _ => None,
+        }
+    }
+
+    /// Returns the code length of each of the 256 byte values.
+    pub fn byte_lengths(&self) -> &'static [u8; 256] {
+        match self {
+            Preset::English => &ENGLISH_LENGTHS,
+            Preset::Rust => &RUST_LENGTHS,
+            Preset::Json => &JSON_LENGTHS,
+            Preset::Uniform => &[8; 256],
+        }
+    }
+
+    /// Returns the (symbol, code length) pairs of the preset, from which the canonical codes are built.
+    pub fn lengths<S: HuffmanSymbol>(&self) -> Vec<(S, usize)> {
+        self.byte_lengths().iter().enumerate().map(|(byte, &len)| (S::from_byte(byte as u8), len as usize)).collect()
+    }
+}
+
+
+// ---------- Tables -----------
+// Each row holds the code lengths of 16 byte values, starting at the one in comment. The lengths were computed with the
+// package-merge algorithm (15 bits at most) on the byte frequencies of samples, each byte being counted once more.
+
+/// Code lengths of the English preset, from the license texts of a Debian system (GPL, Apache, MPL...).
+const ENGLISH_LENGTHS: [u8; 256] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 13,  6, 15, 14, 15, 15, 15, // 0x00
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x10
+     3, 15,  9, 15, 15, 15, 15, 11,  9,  9, 10, 15,  7,  9,  7, 11, // 0x20
+    11, 10, 11, 11, 12, 12, 12, 13, 13, 12, 11, 11, 13, 13, 13, 15, // 0x30
+    15,  8, 10,  8,  9,  8,  9,  9,  9,  8, 14, 13,  8,  9,  8,  8, // 0x40
+     9, 14,  8,  8,  8,  9, 11, 10, 12,  9, 14, 15, 15, 15, 15, 11, // 0x50
+    14,  4,  6,  5,  5,  4,  6,  7,  5,  4, 11,  8,  5,  6,  4,  4, // 0x60
+     6, 10,  4,  4,  4,  5,  7,  7,  9,  6, 13, 15, 15, 15, 15, 15, // 0x70
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x80
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x90
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xA0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xB0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xC0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xD0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xE0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xF0
+];
+
+/// Code lengths of the Rust preset, from the sources of crates (`bitvec` and its dependencies).
+const RUST_LENGTHS: [u8; 256] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15,  4,  5, 15, 15, 15, 15, 15, // 0x00
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x10
+     3,  8,  9,  8,  9, 14,  9, 10,  6,  6,  9, 11,  6,  8,  6,  5, // 0x20
+     8,  8,  9, 10, 11, 12, 10, 13, 10, 14,  6,  7,  7,  8,  7, 11, // 0x30
+    13,  9,  8, 10, 10,  9, 10, 13, 12,  9, 15, 14, 10,  9, 10,  8, // 0x40
+    10, 15,  9,  8,  8, 10, 10, 13, 12, 13, 13,  8, 14,  8, 13,  6, // 0x50
+     7,  5,  6,  6,  6,  4,  6,  7,  6,  4, 13,  9,  5,  6,  5,  5, // 0x60
+     6,  9,  5,  5,  4,  6,  8,  8,  8,  8,  9,  8, 10,  8, 15, 15, // 0x70
+    12, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x80
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 13, 15, 15, 15, 15, 15, 15, // 0x90
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xA0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xB0
+    15, 15, 15, 14, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xC0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xD0
+    15, 15, 13, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xE0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xF0
+];
+
+/// Code lengths of the JSON preset, from indented JSON description files of AWS services.
+const JSON_LENGTHS: [u8; 256] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15,  5, 15, 15, 15, 15, 15, // 0x00
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x10
+     1, 15,  4, 15, 15, 15, 15, 15, 15, 15, 15, 15,  6, 12, 10, 15, // 0x20
+    10, 15, 15, 15, 15, 15, 15, 15, 15, 15,  5, 15, 15, 15, 15, 15, // 0x30
+    14,  9, 11, 10,  9, 12, 12, 10, 15,  7, 15, 11, 12, 11,  9, 11, // 0x40
+     9, 14,  9, 10, 10, 11,  9, 15, 15, 15, 15,  8, 15,  8, 15, 15, // 0x50
+    15,  5,  9,  6,  7,  4,  8,  8,  8,  6, 13,  9,  8,  7,  6,  6, // 0x60
+     7,  9,  5,  6,  5,  6, 10, 10, 14,  8, 15,  7, 15,  7, 15, 15, // 0x70
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x80
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0x90
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xA0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xB0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xC0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xD0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xE0
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, // 0xF0
+];
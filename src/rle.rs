@@ -0,0 +1,176 @@
+use crate::{EncodingMethod, Options};
+use crate::archive::{write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::error::Error;
+use crate::huffman::{build_huffman_archive, decode_huffman_archive};
+
+
+// ---------- PackBits -----------
+// Same layout as the PackBits format of TIFF and MacPaint: the data is a sequence of packets, each starting with a
+// header byte `n`. From 0 to 127, the `n + 1` next bytes are copied as is. From 129 to 255, the next byte is repeated
+// `257 - n` times (2 to 128 times). 128 is never written.
+
+/// Longest run, and largest number of literal bytes, a single packet can hold.
+const MAX_PACKET: usize = 128;
+
+/// Header byte reserved by PackBits, which doesn't stand for any packet.
+const NO_OP: u8 = 128;
+
+
+/// Returns the length of the run of identical bytes at the start of `content`, up to `MAX_PACKET` bytes.
+fn run_len(content: &[u8]) -> usize {
+    content.iter().take(MAX_PACKET).take_while(|&&byte| byte == content[0]).count()
+}
+
+
+/// Returns `content` in PackBits packets.
+///
+/// Runs of 3 bytes or more are always repeat packets. Runs of 2 bytes only are when no literal packet is pending, as
+/// ending the literal packet would cost a header byte more than copying them.
+pub fn pack(content: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let flush = |packed: &mut Vec<u8>, literals: &mut Vec<u8>| {
+        if !literals.is_empty() {
+            packed.push((literals.len() - 1) as u8);
+            packed.append(literals);
+        }
+    };
+
+    let mut pos = 0;
+    while pos < content.len() {
+        let run = run_len(&content[pos..]);
+        if run >= 3 || (run == 2 && literals.is_empty()) {
+            flush(&mut packed, &mut literals);
+            packed.push((257 - run) as u8);
+            packed.push(content[pos]);
+            pos += run;
+            continue;
+        }
+
+        literals.extend_from_slice(&content[pos..pos + run]);
+        if literals.len() >= MAX_PACKET {
+            let rest = literals.split_off(MAX_PACKET);
+            flush(&mut packed, &mut literals);
+            literals = rest;
+        }
+        pos += run;
+    }
+    flush(&mut packed, &mut literals);
+
+    packed
+}
+
+
+/// Decodes PackBits packets written by `pack`, which must give exactly `original_len` bytes.
+pub fn unpack(packed: &[u8], original_len: u64) -> Result<Vec<u8>, Error> {
+    let mut reader = ByteReader::new(packed);
+    let mut content = Vec::new();
+
+    while reader.position() < packed.len() {
+        let start = reader.position();
+        match reader.read_u8()? {
+            NO_OP => return Err(Error::corrupt_at(start, "PackBits no-op header")),
+            header @ 0..NO_OP => content.extend_from_slice(reader.take(header as usize + 1)?),
+            header => {
+                let byte = reader.read_u8()?;
+                content.resize(content.len() + 257 - header as usize, byte);
+            },
+        }
+        if content.len() as u64 > original_len {
+            return Err(Error::corrupt_at(start, "packet goes past the end of the file"));
+        }
+    }
+
+    if content.len() as u64 != original_len {
+        return Err(Error::corrupt(format!("{} bytes unpacked for a file of {} bytes", content.len(), original_len)));
+    }
+    Ok(content)
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Returns the archive of the files `files`, each file being its PackBits packets (see `pack`). There is no method header.
+pub fn encode_rle(files: &[NamedFile]) -> Archive {
+    let members = files.iter().map(|(name, content)| Member {
+        name: name.clone(),
+        original_len: content.len() as u64,
+        payload: pack(content),
+    }).collect();
+
+    Archive { method: EncodingMethod::Rle, method_header: Vec::new(), members }
+}
+
+
+/// Decodes archives written by `encode_rle`, returning the content of each of their files.
+pub fn decode_rle(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    if !archive.method_header.is_empty() {
+        return Err(Error::corrupt_at(0, "unexpected method header"));
+    }
+    archive.members.iter().map(|member| unpack(&member.payload, member.original_len)).collect()
+}
+
+
+/// Returns the archive of the files `files`, packed with PackBits then Huffman coded on bytes with the options of
+/// `options`, as with the `huffman-bytes` method: long runs shrink to 2 bytes before the Huffman coder sees them.
+///
+/// The method header is the one of `build_huffman_archive`. The payload of each file is the `packed length` (varint)
+/// followed by the Huffman stream of its packets.
+pub fn encode_rle_huffman(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    let packed_files: Vec<NamedFile> = files.iter().map(|(name, content)| (name.clone(), pack(content))).collect();
+    let mut archive = build_huffman_archive::<u8>(&packed_files, EncodingMethod::RleHuffman, options.table_mode, options.max_code_length)?;
+
+    for (member, (_, content)) in archive.members.iter_mut().zip(files) {
+        let mut payload = Vec::new();
+        write_varint(&mut payload, member.original_len);
+        payload.append(&mut member.payload);
+        member.payload = payload;
+        member.original_len = content.len() as u64;
+    }
+    Ok(archive)
+}
+
+
+/// Decodes archives written by `encode_rle_huffman`, returning the content of each of their files.
+pub fn decode_rle_huffman(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    // The packets are decoded as a `huffman-bytes` archive of the packed files
+    let mut packed_archive = Archive { method: EncodingMethod::HuffmanBytes, method_header: archive.method_header.clone(), members: Vec::new() };
+    for member in &archive.members {
+        let mut reader = ByteReader::new(&member.payload);
+        let packed_len = reader.read_varint()?;
+        packed_archive.members.push(Member {
+            name: member.name.clone(),
+            original_len: packed_len,
+            payload: member.payload[reader.position()..].to_vec(),
+        });
+    }
+
+    decode_huffman_archive::<u8>(&packed_archive)?.iter()
+        .zip(&archive.members)
+        .map(|(packed, member)| unpack(packed, member.original_len))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_become_repeat_packets() {
+        assert_eq!(pack(b"aaaaabcddd"), vec![252, b'a', 1, b'b', b'c', 254, b'd']);
+        // A pair inside literals stays literal, a pair on its own doesn't
+        assert_eq!(pack(b"abbc"), vec![3, b'a', b'b', b'b', b'c']);
+        assert_eq!(pack(b"bbc"), vec![255, b'b', 0, b'c']);
+
+        // Runs and literal sequences longer than a packet are cut
+        let content = [vec![0; 300], (1..=255).collect(), vec![7; 2]].concat();
+        let packed = pack(&content);
+        assert_eq!(&packed[..6], &[129, 0, 129, 0, 213, 0]);
+        assert_eq!(unpack(&packed, content.len() as u64).unwrap(), content);
+        assert!(unpack(&packed, content.len() as u64 - 1).is_err());
+        assert!(unpack(&[NO_OP], 0).is_err());
+    }
+}
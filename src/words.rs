@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use crate::{EncodingMethod, Options};
+use crate::archive::{write_varint, Archive, ByteReader, Member, NamedFile};
+use crate::error::Error;
+use crate::huffman::{build_canonical_bitvec_map_from_frequencies, decode_symbols, encode_symbols, CanonicalDecoder};
+
+
+// ---------- Tokens -----------
+
+/// Cuts text into tokens: words (runs of alphanumeric characters) and the separators between them (runs of any other
+/// characters, spaces and punctuation). Joining the tokens gives back the text.
+pub fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_word = None;
+
+    for (index, ch) in text.char_indices() {
+        let is_word = ch.is_alphanumeric();
+        if in_word.is_some_and(|in_word| in_word != is_word) {
+            tokens.push(&text[start..index]);
+            start = index;
+        }
+        in_word = Some(is_word);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+
+    tokens
+}
+
+
+/// Checks that the code length limit of the options can be used with a dictionary of `token_count` tokens: a limit of
+/// L bits only allows 2^L codes, so the error names the lowest `-l` which numbers every token.
+fn check_token_code_length(max_code_length: Option<usize>, token_count: usize) -> Result<(), Error> {
+    let needed = token_count.next_power_of_two().trailing_zeros() as usize;
+    match max_code_length {
+        Some(max_len) if max_len < needed => Err(Error::InvalidArgument(format!(
+            "The {} distinct words and separators of the files need codes of up to {} bits: use -l {} or more (or -l 0 for no limit).",
+            token_count, needed, needed
+        ))),
+        _ => Ok(()),
+    }
+}
+
+
+// #################################
+// ------- Dictionary --------------
+// #################################
+
+/// Returns the dictionary stored in the method header: `token count` (varint), then for each token, in byte order,
+/// `shared prefix` (varint, number of bytes it shares with the previous token), `suffix length` (varint), the suffix
+/// and `code length` (u8).
+///
+/// Sorted tokens share long prefixes (`compress`, `compressed`, `compression`...), so each one only stores its end.
+fn write_dictionary(tokens: &[&str], lengths: &[usize]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, tokens.len() as u64);
+
+    let mut previous: &[u8] = &[];
+    for (token, &length) in tokens.iter().zip(lengths) {
+        let token = token.as_bytes();
+        let prefix = previous.iter().zip(token).take_while(|(a, b)| a == b).count();
+
+        write_varint(&mut bytes, prefix as u64);
+        write_varint(&mut bytes, (token.len() - prefix) as u64);
+        bytes.extend_from_slice(&token[prefix..]);
+        bytes.push(length as u8);
+        previous = token;
+    }
+
+    bytes
+}
+
+
+/// Dictionary read from the method header: the tokens, their id being their index, and the code length of each id.
+struct Dictionary {
+    tokens: Vec<String>,
+    lengths: Vec<(u32, usize)>,
+}
+
+
+/// Reads the dictionary written by `write_dictionary`.
+fn read_dictionary(reader: &mut ByteReader) -> Result<Dictionary, Error> {
+    let token_count = reader.read_varint()?;
+    let mut tokens: Vec<String> = Vec::new();
+    let mut lengths = Vec::new();
+
+    for id in 0..token_count {
+        let id = u32::try_from(id).map_err(|_| Error::corrupt_at(reader.position(), "too many tokens in the dictionary"))?;
+        let prefix = reader.read_varint()? as usize;
+        let suffix_len = reader.read_varint()? as usize;
+
+        let previous = tokens.last().map_or(&[][..], |token| token.as_bytes());
+        if prefix > previous.len() {
+            return Err(Error::corrupt_at(reader.position(), "token shares more bytes than the previous token has"));
+        }
+        let mut token = previous[..prefix].to_vec();
+        token.extend_from_slice(reader.take(suffix_len)?);
+
+        if token.is_empty() {
+            return Err(Error::corrupt_at(reader.position(), "empty token in the dictionary"));
+        }
+        let token = String::from_utf8(token).map_err(|_| Error::corrupt_at(reader.position(), "token is not valid UTF-8"))?;
+
+        tokens.push(token);
+        lengths.push((id, reader.read_u8()? as usize));
+    }
+
+    Ok(Dictionary { tokens, lengths })
+}
+
+
+// #################################
+// ------- Encoding / decoding -----
+// #################################
+
+/// Returns the archive of the files `files`, using Huffman coding on the words and separators of the files, which must
+/// be UTF-8 text.
+///
+/// All files share one dictionary, stored in the method header (see `write_dictionary`), and one canonical code over
+/// its tokens. The payload of each file is the bitstream of the codes of its tokens (see `BitWriter::finish`).
+pub fn encode_word_huffman(files: &[NamedFile], options: &Options) -> Result<Archive, Error> {
+    let mut texts = Vec::new();
+    for (name, content) in files {
+        let text = str::from_utf8(content)
+            .map_err(|err| Error::InvalidArgument(format!("{} is not UTF-8 text ({}), the words method only compresses text.", name, err)))?;
+        texts.push(tokenize(text));
+    }
+
+    // Tokens are numbered in byte order, which is the order of the dictionary
+    let mut tokens: Vec<&str> = texts.iter().flatten().copied().collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+    let ids: HashMap<&str, u32> = tokens.iter().enumerate().map(|(id, &token)| (token, id as u32)).collect();
+
+    let id_texts: Vec<Vec<u32>> = texts.iter().map(|text| text.iter().map(|token| ids[token]).collect()).collect();
+    let mut frequencies: HashMap<u32, u64> = HashMap::new();
+    for &id in id_texts.iter().flatten() {
+        *frequencies.entry(id).or_insert(0) += 1;
+    }
+
+    check_token_code_length(options.max_code_length, tokens.len())?;
+    let code_map = build_canonical_bitvec_map_from_frequencies(&frequencies, options.max_code_length)?;
+    let lengths: Vec<usize> = (0..tokens.len() as u32).map(|id| code_map[&id].len()).collect();
+
+    let mut members = Vec::new();
+    for ((name, content), id_text) in files.iter().zip(&id_texts) {
+        members.push(Member {
+            name: name.clone(),
+            original_len: content.len() as u64,
+            payload: encode_symbols(id_text.iter().copied(), &code_map)?,
+        });
+    }
+
+    Ok(Archive { method: EncodingMethod::Words, method_header: write_dictionary(&tokens, &lengths), members })
+}
+
+
+/// Decodes archives written by `encode_word_huffman`, returning the content of each of their files.
+pub fn decode_word_huffman(archive: &Archive) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = ByteReader::new(&archive.method_header);
+    let Dictionary { tokens, lengths } = read_dictionary(&mut reader)?;
+    if reader.position() != archive.method_header.len() {
+        return Err(Error::corrupt_at(reader.position(), "unexpected data after the dictionary"));
+    }
+
+    let decoder = CanonicalDecoder::from_lengths(&lengths)?;
+
+    let mut contents = Vec::new();
+    for member in &archive.members {
+        let ids = decode_symbols(&member.payload, &decoder, member.original_len, |id| tokens[id as usize].len() as u64)?;
+        contents.push(ids.into_iter().flat_map(|id| tokens[id as usize].bytes()).collect());
+    }
+
+    Ok(contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_join_back_into_the_text() {
+        let text = "Hello, world!  It's 2024 — été.\n";
+        let tokens = tokenize(text);
+        assert_eq!(tokens, vec!["Hello", ", ", "world", "!  ", "It", "'", "s", " ", "2024", " — ", "été", ".\n"]);
+        assert_eq!(tokens.concat(), text);
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn dictionary_round_trips_prefixes_cut_inside_characters() {
+        // "è" and "é" share their first UTF-8 byte only
+        let tokens = ["compress", "compressed", "compression", "è", "é"];
+        let lengths = [2, 3, 3, 2, 2];
+        let bytes = write_dictionary(&tokens, &lengths);
+
+        let dictionary = read_dictionary(&mut ByteReader::new(&bytes)).unwrap();
+        assert_eq!(dictionary.tokens, tokens);
+        assert_eq!(dictionary.lengths, vec![(0, 2), (1, 3), (2, 3), (3, 2), (4, 2)]);
+    }
+
+    #[test]
+    fn code_length_limit_must_number_every_token() {
+        // 6 distinct tokens need codes of 3 bits
+        let files = vec![("a.txt".to_string(), b"one two, three four".to_vec())];
+        let options = |max_code_length| Options { max_code_length, ..Options::with_encoding(EncodingMethod::Words) };
+
+        match encode_word_huffman(&files, &options(Some(2))) {
+            Err(Error::InvalidArgument(message)) => assert!(message.contains("-l 3"), "{}", message),
+            _ => panic!("a limit of 2 bits can't code 6 tokens"),
+        }
+        for max_code_length in [Some(3), None] {
+            let archive = encode_word_huffman(&files, &options(max_code_length)).unwrap();
+            assert_eq!(decode_word_huffman(&archive).unwrap()[0], files[0].1);
+        }
+    }
+
+    #[test]
+    fn default_options_code_more_tokens_than_15_bits_number() {
+        let text: String = (0..40_000).map(|index| format!("w{} ", index)).collect();
+        let files = vec![("big.txt".to_string(), text.into_bytes())];
+
+        let archive = encode_word_huffman(&files, &Options::with_encoding(EncodingMethod::Words)).unwrap();
+        assert_eq!(decode_word_huffman(&archive).unwrap()[0], files[0].1);
+    }
+}